@@ -1,5 +1,6 @@
 
 import bisect
+import json
 from dataclasses import dataclass, field
 from typing import Optional, Tuple
 
@@ -121,3 +122,40 @@ class Animation:
 	def remove_point(self, point_idx: int):
 		for f in self._keyframes:
 			f.remove_point(point_idx)
+
+	def to_json(self) -> dict:
+		"""Keyframe-major points in the schema the Rust tool reads, KeyframeFile in src/landmarks.rs:
+		{"version": 1, "keyframes": [{"frame": int, "points_left": [[x, y], ...], "points_right": [[x, y], ...]}, ...]}
+		Every keyframe has every point, and the nth point of each keyframe becomes channel n."""
+		return {
+			"version": 1,
+			"keyframes": [
+				{
+					"frame": int(k.frame),
+					"points_left": k.points_left.tolist(),
+					"points_right": k.points_right.tolist(),
+				}
+				for k in self._keyframes
+			],
+		}
+
+	def save_json(self, filename: str):
+		with open(filename, 'w') as fout:
+			json.dump(self.to_json(), fout, indent=2)
+
+	@classmethod
+	def from_json(cls, data: dict) -> 'Animation':
+		assert data["version"] == 1, f"Unsupported keyframe file version {data['version']}."
+		animation = cls()
+		for k in sorted(data["keyframes"], key=lambda k: k["frame"]):
+			animation._keyframes.append(Keyframe(
+				frame=int(k["frame"]),
+				points_left=numpy.asarray(k["points_left"], dtype=float).reshape(-1, 2),
+				points_right=numpy.asarray(k["points_right"], dtype=float).reshape(-1, 2),
+			))
+		return animation
+
+	@classmethod
+	def load_json(cls, filename: str) -> 'Animation':
+		with open(filename, 'r') as fin:
+			return cls.from_json(json.load(fin))
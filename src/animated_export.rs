@@ -0,0 +1,256 @@
+// Writing a morph straight to a single animated file, GIF or APNG, for sharing in chat and docs.
+// Frames are rendered up front, so this is meant for short clips at modest sizes.  Use `export` for anything big.
+
+use anyhow::{bail, Result};
+use color_quant::NeuQuant;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::ColorMap;
+use image::{Delay, DynamicImage, Frame, Rgba, RgbaImage};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::export::{blend_amount, render_animation_frame, ExportInput};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum AnimatedFormat {
+	/// Palette size is 2-256 colors per frame.  Speed trades quantization quality (1) for time (30).
+	/// Dithering smooths out banding in gradients at the cost of some noise and a bigger file.
+	/// GIF transparency is on or off, so alpha is cut at half.
+	Gif { palette_size: u16, dither: bool, speed: i32 },
+	/// Full color and alpha, but not every viewer animates it.
+	Apng,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct AnimatedSettings {
+	pub format: AnimatedFormat,
+	pub fps: f32,
+	/// How many times to play.  Zero loops forever.
+	pub loop_count: u16,
+	/// Play forward and then backward, left to right to left.
+	pub ping_pong: bool,
+}
+
+impl Default for AnimatedSettings {
+	fn default() -> Self {
+		AnimatedSettings {
+			format: AnimatedFormat::Gif { palette_size: 256, dither: true, speed: 10 },
+			fps: 24.0,
+			loop_count: 0,
+			ping_pong: false,
+		}
+	}
+}
+
+/// The order to show `num_frames` rendered frames in.  Ping-pong doesn't repeat the frames at either end,
+/// so it loops without a stutter.
+pub fn playback_order(num_frames: usize, ping_pong: bool) -> Vec<usize> {
+	let mut order: Vec<usize> = (0..num_frames).collect();
+	if ping_pong && num_frames > 2 {
+		order.extend((1..num_frames - 1).rev());
+	}
+	order
+}
+
+/// Render every frame in the range and write them as one animated file.
+pub fn export_animated(input: &mut ExportInput, frames: RangeInclusive<u32>, settings: &AnimatedSettings, path: &Path) -> Result<()> {
+	if settings.fps.is_nan() || settings.fps <= 0.0 {
+		bail!("Frame rate must be positive, not {}.", settings.fps);
+	}
+	if frames.is_empty() {
+		bail!("There are no frames to export.");
+	}
+	let mut rendered = vec![];
+	for frame in frames.clone() {
+		let image = render_animation_frame(input, frame, blend_amount(frame, &frames))?;
+		rendered.push(DynamicImage::ImageRgba32F(image).to_rgba8());
+	}
+	let writer = BufWriter::new(File::create(path)?);
+	write_animated(&rendered, settings, writer)
+}
+
+/// Write already rendered frames as an animated file.  Every frame has to be the same size.
+pub fn write_animated<W: Write>(frames: &[RgbaImage], settings: &AnimatedSettings, writer: W) -> Result<()> {
+	let Some(first) = frames.first() else {
+		bail!("There are no frames to export.");
+	};
+	if frames.iter().any(|f| f.dimensions() != first.dimensions()) {
+		bail!("Every frame of an animation has to be the same size.");
+	}
+	let order = playback_order(frames.len(), settings.ping_pong);
+	match settings.format {
+		AnimatedFormat::Gif { palette_size, dither, speed } => write_gif(frames, &order, settings, palette_size, dither, speed, writer),
+		AnimatedFormat::Apng => write_apng(frames, &order, settings, writer),
+	}
+}
+
+fn write_gif<W: Write>(frames: &[RgbaImage], order: &[usize], settings: &AnimatedSettings, palette_size: u16, dither: bool, speed: i32, writer: W) -> Result<()> {
+	let speed = speed.clamp(1, 30);
+	let palette_size = palette_size.clamp(2, 256) as usize;
+	// Quantize each frame once, even if ping-pong shows it twice.
+	let quantized: Vec<RgbaImage> = frames.iter().map(|f| quantize(f, palette_size, dither, speed)).collect();
+
+	let mut encoder = GifEncoder::new_with_speed(writer, speed);
+	// GIF counts the repeats after the first play, where APNG counts plays.
+	encoder.set_repeat(if settings.loop_count == 0 { Repeat::Infinite } else { Repeat::Finite(settings.loop_count - 1) })?;
+	// Hundredths of a frame per second, like APNG, so fractional rates aren't rounded to whole ones first.
+	let delay = Delay::from_numer_denom_ms(100_000, (settings.fps * 100.0).round().max(1.0) as u32);
+	for &idx in order {
+		// With no more than 256 colors left, the encoder uses them as the palette exactly instead of quantizing again.
+		encoder.encode_frame(Frame::from_parts(quantized[idx].clone(), 0, 0, delay))?;
+	}
+	Ok(())
+}
+
+/// Reduce a frame to at most `palette_size` colors plus transparency, optionally with Floyd-Steinberg dithering.
+fn quantize(frame: &RgbaImage, palette_size: usize, dither: bool, speed: i32) -> RgbaImage {
+	let mut image = frame.clone();
+	let mut has_transparency = false;
+	for p in image.pixels_mut() {
+		if p[3] < 128 {
+			*p = Rgba([0, 0, 0, 0]);
+			has_transparency = true;
+		} else {
+			p[3] = 255;
+		}
+	}
+	// Leave a slot for the transparent color.
+	let colors = if has_transparency { palette_size.min(255) } else { palette_size };
+	let quantizer = NeuQuant::new(speed, colors, image.as_raw());
+	let transparent: Vec<bool> = image.pixels().map(|p| p[3] == 0).collect();
+	if dither {
+		image::imageops::dither(&mut image, &quantizer);
+	} else {
+		for p in image.pixels_mut() {
+			quantizer.map_color(p);
+		}
+	}
+	// Put the transparency back exactly, in case the palette or the error diffusion blurred it.
+	for (p, clear) in image.pixels_mut().zip(transparent) {
+		if clear {
+			*p = Rgba([0, 0, 0, 0]);
+		} else {
+			p[3] = 255;
+		}
+	}
+	image
+}
+
+fn write_apng<W: Write>(frames: &[RgbaImage], order: &[usize], settings: &AnimatedSettings, writer: W) -> Result<()> {
+	// The image crate can read APNG but not write it, so this goes through png directly.
+	let (width, height) = frames[0].dimensions();
+	let mut encoder = png::Encoder::new(writer, width, height);
+	encoder.set_color(png::ColorType::Rgba);
+	encoder.set_depth(png::BitDepth::Eight);
+	encoder.set_animated(order.len() as u32, settings.loop_count as u32)?;
+	// The delay is a fraction of a second.  Hundredths keep fractional rates like 29.97 close.
+	encoder.set_frame_delay(100, (settings.fps * 100.0).round().clamp(1.0, u16::MAX as f32) as u16)?;
+	let mut png_writer = encoder.write_header()?;
+	for &idx in order {
+		png_writer.write_image_data(frames[idx].as_raw())?;
+	}
+	png_writer.finish()?;
+	Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::export::tests::{black_to_white, scratch_directory};
+	use crate::morph::{Dissolve, MorphSettings};
+	use image::codecs::gif::GifDecoder;
+	use image::codecs::png::PngDecoder;
+	use image::AnimationDecoder;
+
+	#[test]
+	fn test_playback_order() {
+		assert_eq!(playback_order(4, false), vec![0, 1, 2, 3]);
+		assert_eq!(playback_order(4, true), vec![0, 1, 2, 3, 2, 1]);
+		assert_eq!(playback_order(2, true), vec![0, 1]);
+	}
+
+	#[test]
+	fn test_export_gif_and_apng() {
+		let directory = scratch_directory("animated");
+		std::fs::create_dir_all(&directory).unwrap();
+		let (animation, mut left, mut right) = black_to_white();
+		let mut input = ExportInput { animation: &animation, left: &mut left, right: &mut right, morph_settings: &MorphSettings::default(), dissolve: &Dissolve::Uniform };
+
+		let gif_path = directory.join("morph.gif");
+		let settings = AnimatedSettings { ping_pong: true, fps: 10.0, ..Default::default() };
+		export_animated(&mut input, 0..=4, &settings, &gif_path).unwrap();
+		let frames = GifDecoder::new(File::open(&gif_path).unwrap()).unwrap().into_frames().collect_frames().unwrap();
+		assert_eq!(frames.len(), 8);
+		assert_eq!(frames[0].delay(), Delay::from_numer_denom_ms(100, 1));
+		assert_eq!(frames[0].buffer().get_pixel(3, 3).0, [0, 0, 0, 255]);
+		assert_eq!(frames[4].buffer().get_pixel(3, 3).0, [255, 255, 255, 255]);
+		assert_eq!(frames[5].buffer(), frames[3].buffer());
+
+		let apng_path = directory.join("morph.png");
+		let settings = AnimatedSettings { format: AnimatedFormat::Apng, loop_count: 3, ..Default::default() };
+		export_animated(&mut input, 0..=4, &settings, &apng_path).unwrap();
+		let decoder = PngDecoder::new(File::open(&apng_path).unwrap()).unwrap();
+		let frames = decoder.apng().into_frames().collect_frames().unwrap();
+		assert_eq!(frames.len(), 5);
+		assert_eq!(frames[2].buffer().get_pixel(3, 3).0, [188, 188, 188, 255]);
+		std::fs::remove_dir_all(&directory).unwrap();
+	}
+
+	#[test]
+	fn test_fractional_frame_rate() {
+		let frames = vec![RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255])); 2];
+		// Rounded to a whole rate first this would be 3 fps, 330 ms.
+		let settings = AnimatedSettings { fps: 2.5, ..Default::default() };
+		let mut data = vec![];
+		write_animated(&frames, &settings, &mut data).unwrap();
+		let decoded = GifDecoder::new(data.as_slice()).unwrap().into_frames().collect_frames().unwrap();
+		let (numerator, denominator) = decoded[0].delay().numer_denom_ms();
+		assert_eq!(numerator as f32 / denominator as f32, 400.0);
+	}
+
+	#[test]
+	fn test_bad_frames() {
+		let mixed = vec![RgbaImage::new(4, 4), RgbaImage::new(4, 5)];
+		for format in [AnimatedFormat::Apng, AnimatedSettings::default().format] {
+			let settings = AnimatedSettings { format, ..Default::default() };
+			assert!(write_animated(&[], &settings, vec![]).is_err());
+			assert!(write_animated(&mixed, &settings, vec![]).is_err());
+		}
+	}
+
+	#[test]
+	fn test_loop_count() {
+		let frames = vec![RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255])); 2];
+		for (loop_count, gif_repeats) in [(1u16, 0u16), (3, 2), (0, 0)] {
+			let mut data = vec![];
+			write_animated(&frames, &AnimatedSettings { loop_count, ..Default::default() }, &mut data).unwrap();
+			// The NETSCAPE2.0 block holds the repeat count, and leaving it out plays once.
+			let block = data.windows(11).position(|w| w == b"NETSCAPE2.0").map(|i| u16::from_le_bytes([data[i + 13], data[i + 14]]));
+			assert_eq!(block, if loop_count == 1 { None } else { Some(gif_repeats) }, "{loop_count}");
+
+			let mut data = vec![];
+			write_animated(&frames, &AnimatedSettings { format: AnimatedFormat::Apng, loop_count, ..Default::default() }, &mut data).unwrap();
+			let reader = png::Decoder::new(data.as_slice()).read_info().unwrap();
+			assert_eq!(reader.info().animation_control.unwrap().num_plays, loop_count as u32);
+		}
+	}
+
+	#[test]
+	fn test_quantize() {
+		// A smooth gradient with a transparent corner.
+		let mut frame = RgbaImage::from_fn(64, 64, |x, y| Rgba([(x * 4) as u8, (y * 4) as u8, 128, 255]));
+		frame.put_pixel(0, 0, Rgba([200, 10, 10, 20]));
+		for dither in [false, true] {
+			let quantized = quantize(&frame, 16, dither, 10);
+			let mut colors: Vec<[u8; 4]> = quantized.pixels().map(|p| p.0).collect();
+			colors.sort();
+			colors.dedup();
+			assert!(colors.len() <= 17, "{} colors", colors.len());
+			assert_eq!(quantized.get_pixel(0, 0).0, [0, 0, 0, 0]);
+			assert!(quantized.pixels().skip(1).all(|p| p[3] == 255));
+		}
+	}
+}
@@ -62,11 +62,12 @@ impl Animation {
 	/// Find a linear interpolation of this channel at the current frame.
 	/// Will clamp to the 0th and last frames.
 	fn interpolate_point(&self, frame: u32, channel: usize) -> (Point, Point) {
-		let next_frame_idx = self.get_nearest_keyframe_idx(frame, channel);
+		// Past the last keyframe the search gives one past the end, so pull it back in.  Channels are never empty.
+		let next_frame_idx = self.get_nearest_keyframe_idx(frame, channel).min(self.channels[channel].len() - 1);
 		let previous_frame_idx = next_frame_idx.saturating_sub(1);
 		let next_frame = self.channels[channel][next_frame_idx].frame;
 		let previous_frame = self.channels[channel][previous_frame_idx].frame;
-		if next_frame_idx == previous_frame_idx {
+		if next_frame_idx == previous_frame_idx || frame >= next_frame {
 			(self.channels[channel][next_frame_idx].left.clone(), self.channels[channel][next_frame_idx].right.clone())
 		} else {
 			let amount = (frame as f32 - previous_frame as f32) / (next_frame as f32 - previous_frame as f32);
@@ -120,8 +121,8 @@ impl Animation {
 	}
 
 	/// Remove the given channel keypoint.
-	/// If frame is None, will remove all keypoints from the channel and delete the channel.
-	/// If a channel is deleted, remaining channel indices should be shifted down.
+	/// If frame is None, or the cleared keypoint was the channel's last, the whole channel is deleted.
+	/// If a channel is deleted, remaining channel indices are shifted down.
 	/// If clear_point is called on a frame or channel that does not exist, this method will panic.
 	pub fn clear_point(&mut self, frame: Option<u32>, channel_idx: usize) {
 		if let Some(f) = frame {
@@ -129,7 +130,11 @@ impl Animation {
 			if self.channels[channel_idx][nearest_keyframe_idx].frame != f {
 				panic!("Attempted to remove keyframe {f} on channel {channel_idx} which does not exist.");
 			}
-			self.channels[channel_idx].swap_remove(nearest_keyframe_idx); // Okay to use swap_remove INSIDE channels.
+			// Not swap_remove, since the keypoints have to stay sorted by frame for the search.
+			self.channels[channel_idx].remove(nearest_keyframe_idx);
+			if self.channels[channel_idx].is_empty() {
+				self.channels.remove(channel_idx);
+			}
 		} else {
 			self.channels.remove(channel_idx); // Can't swap-remove this, though.
 		}
@@ -137,7 +142,7 @@ impl Animation {
 
 	/// Return a tuple of left and right points, linearly interpolated by frame.
 	/// Each vec contains [x, y, x, y, ...], a vector with 2*num channels elements.
-	/// If frame is greater than the last frame, this method will panic.
+	/// Frames before the first or after the last keyframe of a channel hold that keyframe's points.
 	pub fn get_points(&self, frame: u32) -> (Vec<f32>, Vec<f32>) {
 		let mut left_points = vec![];
 		let mut right_points = vec![];
@@ -156,6 +161,14 @@ impl Animation {
 	pub fn get_num_channels(&self) -> usize {
 		self.channels.len()
 	}
+
+	/// Every frame which has a keypoint in any channel, in order.
+	pub fn get_keyframes(&self) -> Vec<u32> {
+		let mut frames: Vec<u32> = self.channels.iter().flatten().map(|k| k.frame).collect();
+		frames.sort();
+		frames.dedup();
+		frames
+	}
 }
 
 
@@ -205,4 +218,45 @@ mod tests {
 		assert_eq!(anim.get_num_channels(), 1);
 
 	}
+
+	#[test]
+	fn test_hold_past_last_keyframe() {
+		let mut anim = Animation::new();
+		let channel = anim.set_point(0.0, 0.0, 1.0, 1.0, 10, None);
+		anim.set_point(2.0, 2.0, 3.0, 3.0, 20, Some(channel));
+		assert_eq!(anim.get_points(0), (vec![0.0, 0.0], vec![1.0, 1.0]));
+		assert_eq!(anim.get_points(15), (vec![1.0, 1.0], vec![2.0, 2.0]));
+		assert_eq!(anim.get_points(500), (vec![2.0, 2.0], vec![3.0, 3.0]));
+		anim.set_point(0.0, 0.0, 0.0, 0.0, 15, None);
+		assert_eq!(anim.get_keyframes(), vec![10, 15, 20]);
+	}
+
+	#[test]
+	fn test_cleared_channel() {
+		let mut anim = Animation::new();
+		anim.set_point(0.0, 0.0, 1.0, 1.0, 0, None);
+		let channel = anim.set_point(2.0, 2.0, 3.0, 3.0, 5, None);
+		anim.clear_point(Some(5), channel);
+		assert_eq!(anim.get_num_channels(), 1);
+		assert_eq!(anim.get_points(5), (vec![0.0, 0.0], vec![1.0, 1.0]));
+	}
+
+	#[test]
+	fn test_cleared_middle_channel() {
+		let mut anim = Animation::new();
+		anim.set_point(0.0, 0.0, 1.0, 1.0, 0, None);
+		let middle = anim.set_point(2.0, 2.0, 3.0, 3.0, 0, None);
+		anim.set_point(4.0, 4.0, 5.0, 5.0, 0, None);
+		anim.set_point(6.0, 6.0, 7.0, 7.0, 10, Some(middle));
+		// Clearing one of two keypoints keeps the channel.
+		anim.clear_point(Some(0), middle);
+		assert_eq!(anim.get_num_channels(), 3);
+		assert_eq!(anim.get_points(0).0, vec![0.0, 0.0, 6.0, 6.0, 4.0, 4.0]);
+		// Clearing the last one removes it, and the channel after it moves down into its index.
+		anim.clear_point(Some(10), middle);
+		assert_eq!(anim.get_num_channels(), 2);
+		assert_eq!(anim.get_points(0), (vec![0.0, 0.0, 4.0, 4.0], vec![1.0, 1.0, 5.0, 5.0]));
+		anim.set_point(8.0, 8.0, 9.0, 9.0, 10, Some(1));
+		assert_eq!(anim.get_points(10).0, vec![0.0, 0.0, 8.0, 8.0]);
+	}
 }
@@ -0,0 +1,139 @@
+// Carry annotations (tracking points, outlines, segmentation masks) through a morph so they stay aligned with the frames.
+// Frames are rendered by pulling pixels: the warp is fit from the output frame back into each source image.
+// Masks are sampled exactly the same way.  Points and polylines live in the source image, so they usually need
+// to go the other way, through `transform_inverse`.
+
+use anyhow::{anyhow, Result};
+use image::{ImageBuffer, Luma, Primitive};
+
+use crate::warp::Warp;
+
+/// Which way to push points through a warp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+	/// `Warp::transform`.  Use this if the warp was fit from the annotations' image to the output.
+	Forward,
+	/// `Warp::transform_inverse`.  Use this with the warp used for rendering, which maps output to source.
+	Inverse,
+}
+
+/// Map points in the form [x, y, x, y, ...] through the warp.
+/// Fails if the inverse is asked for and the warp can't invert.
+pub fn warp_points(warp: &dyn Warp, points: &Vec<f32>, direction: Direction) -> Result<Vec<f32>> {
+	match direction {
+		Direction::Forward => Ok(warp.transform(points)),
+		Direction::Inverse => warp.transform_inverse(points).ok_or_else(|| anyhow!("This warp doesn't support inversion.  Fit one in the other direction and use Direction::Forward.")),
+	}
+}
+
+/// Map a polyline, [x, y, x, y, ...], through the warp.
+/// A straight edge doesn't stay straight under a nonlinear warp, so edges longer than `max_segment_length` pixels are
+/// subdivided first and the result has more vertices than the input.  The original vertices are all kept, in order.
+/// If `closed`, the edge from the last vertex back to the first is subdivided too, but the first vertex isn't repeated.
+pub fn warp_polyline(warp: &dyn Warp, polyline: &Vec<f32>, closed: bool, max_segment_length: f32, direction: Direction) -> Result<Vec<f32>> {
+	assert_eq!(polyline.len() % 2, 0);
+	assert!(max_segment_length > 0.0);
+	let vertices: Vec<&[f32]> = polyline.chunks_exact(2).collect();
+	let mut dense = Vec::with_capacity(polyline.len());
+	let num_edges = if closed { vertices.len() } else { vertices.len().saturating_sub(1) };
+	for (i, start) in vertices.iter().enumerate() {
+		dense.extend_from_slice(start);
+		if i >= num_edges {
+			continue;
+		}
+		let end = vertices[(i + 1) % vertices.len()];
+		let (dx, dy) = (end[0] - start[0], end[1] - start[1]);
+		let steps = ((dx*dx + dy*dy).sqrt() / max_segment_length).ceil().max(1.0) as usize;
+		for s in 1..steps {
+			let t = s as f32 / steps as f32;
+			dense.push(start[0] + t*dx);
+			dense.push(start[1] + t*dy);
+		}
+	}
+	warp_points(warp, &dense, direction)
+}
+
+/// Warp a single channel mask into an output_width x output_height frame.
+/// The warp maps output pixels back into the mask, the same as the one used to render the frame.
+/// Sampling is nearest neighbor so label values are never blended, and anything outside the mask is zero.
+pub fn warp_mask<T: Primitive>(warp: &dyn Warp, mask: &ImageBuffer<Luma<T>, Vec<T>>, output_width: u32, output_height: u32) -> ImageBuffer<Luma<T>, Vec<T>> {
+	let mut result = ImageBuffer::new(output_width, output_height);
+	// A row at a time keeps the intermediate point buffers small for big frames.
+	let mut row = Vec::with_capacity(2 * output_width as usize);
+	for y in 0..output_height {
+		row.clear();
+		for x in 0..output_width {
+			row.push(x as f32);
+			row.push(y as f32);
+		}
+		let sources = warp.transform(&row);
+		for (x, source) in sources.chunks_exact(2).enumerate() {
+			let (sx, sy) = (source[0].round(), source[1].round());
+			if sx >= 0.0 && sy >= 0.0 && sx < mask.width() as f32 && sy < mask.height() as f32 {
+				result.put_pixel(x as u32, y, *mask.get_pixel(sx as u32, sy as u32));
+			}
+		}
+	}
+	result
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::moving_least_squares::MlsMode;
+	use crate::thin_plate_spline::{RadialBasis, ThinPlateSpline};
+	use crate::warp::WarpMethod;
+	use image::GrayImage;
+
+	// Output pixel (x, y) samples the source at (x - 10, y - 5), so content moves right 10 and down 5.
+	fn render_warp() -> ThinPlateSpline {
+		let output = vec![0.0f32, 0.0, 100.0, 0.0, 0.0, 100.0, 100.0, 100.0];
+		let source: Vec<f32> = output.chunks_exact(2).flat_map(|p| [p[0] - 10.0, p[1] - 5.0]).collect();
+		ThinPlateSpline::new(&output, &source, 0.0)
+	}
+
+	#[test]
+	fn test_warp_points() {
+		let warp = render_warp();
+		let moved = warp_points(&warp, &vec![20.0, 30.0], Direction::Inverse).unwrap();
+		assert!((moved[0] - 30.0).abs() < 1e-2 && (moved[1] - 35.0).abs() < 1e-2, "{moved:?}");
+
+		// MLS can't invert, so that has to be an error rather than a silent wrong answer.
+		let mls = WarpMethod::MovingLeastSquares { mode: MlsMode::Rigid, alpha: 1.0 }.fit(&vec![0.0, 0.0, 10.0, 0.0, 0.0, 10.0], &vec![0.0, 0.0, 10.0, 0.0, 0.0, 10.0]);
+		assert!(warp_points(mls.as_ref(), &vec![1.0, 1.0], Direction::Inverse).is_err());
+		assert!(warp_points(mls.as_ref(), &vec![1.0, 1.0], Direction::Forward).is_ok());
+	}
+
+	#[test]
+	fn test_warp_polyline_subdivides() {
+		let warp = WarpMethod::ThinPlateSpline { alpha: Some(0.0), kernel: RadialBasis::ThinPlate }.fit(
+			&vec![0.0, 0.0, 100.0, 0.0, 0.0, 100.0, 100.0, 100.0],
+			&vec![0.0, 0.0, 100.0, 0.0, 0.0, 100.0, 100.0, 100.0],
+		);
+		// A 40x40 square with 10px segments: four edges of four segments each.
+		let square = vec![10.0f32, 10.0, 50.0, 10.0, 50.0, 50.0, 10.0, 50.0];
+		let closed = warp_polyline(warp.as_ref(), &square, true, 10.0, Direction::Forward).unwrap();
+		assert_eq!(closed.len(), 2 * 16);
+		let open = warp_polyline(warp.as_ref(), &square, false, 10.0, Direction::Forward).unwrap();
+		assert_eq!(open.len(), 2 * 13);
+		// Original vertices survive in order.
+		assert!((open[8] - 50.0).abs() < 1e-2 && (open[9] - 10.0).abs() < 1e-2);
+		assert!((open[24] - 10.0).abs() < 1e-2 && (open[25] - 50.0).abs() < 1e-2);
+	}
+
+	#[test]
+	fn test_warp_mask() {
+		let mut mask = GrayImage::new(40, 40);
+		mask.put_pixel(3, 4, Luma([7]));
+		mask.put_pixel(39, 39, Luma([9]));
+		let warped = warp_mask(&render_warp(), &mask, 50, 50);
+		assert_eq!(warped.dimensions(), (50, 50));
+		assert_eq!(warped.get_pixel(13, 9)[0], 7);
+		assert_eq!(warped.get_pixel(49, 44)[0], 9);
+		// Only label values, never anything in between, and the uncovered border is empty.
+		assert!(warped.pixels().all(|p| [0, 7, 9].contains(&p[0])));
+		assert_eq!(warped.get_pixel(2, 2)[0], 0);
+		assert_eq!(warped.pixels().filter(|p| p[0] != 0).count(), 2);
+	}
+}
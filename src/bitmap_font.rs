@@ -0,0 +1,74 @@
+// A tiny built-in pixel font for labels and captions drawn onto frames, so nothing needs a font file.
+// It only covers what those use: digits and a little punctuation.
+
+use image::{ImageBuffer, Pixel};
+
+// Glyphs are 3x5 pixels with one pixel of spacing.
+pub const GLYPH_WIDTH: u32 = 3;
+pub const GLYPH_HEIGHT: u32 = 5;
+
+/// Rows of a glyph, top to bottom, with the left pixel in bit 2.  Characters without a glyph are left blank.
+fn glyph(c: char) -> Option<[u8; 5]> {
+	Some(match c {
+		'0' => [7, 5, 5, 5, 7],
+		'1' => [2, 6, 2, 2, 7],
+		'2' => [7, 1, 7, 4, 7],
+		'3' => [7, 1, 7, 1, 7],
+		'4' => [5, 5, 7, 1, 1],
+		'5' => [7, 4, 7, 1, 7],
+		'6' => [7, 4, 7, 5, 7],
+		'7' => [7, 1, 1, 1, 1],
+		'8' => [7, 5, 7, 5, 7],
+		'9' => [7, 5, 7, 1, 7],
+		'.' => [0, 0, 0, 0, 2],
+		'#' => [5, 7, 5, 7, 5],
+		'%' => [5, 1, 2, 4, 5],
+		'-' => [0, 0, 7, 0, 0],
+		':' => [0, 2, 0, 2, 0],
+		'/' => [1, 1, 2, 4, 4],
+		_ => return None,
+	})
+}
+
+/// Draw text with the built-in pixel font, its top left corner at (x, y), clipped to the image.
+pub fn draw_text<P: Pixel>(image: &mut ImageBuffer<P, Vec<P::Subpixel>>, x: u32, y: u32, text: &str, scale: u32, color: P) {
+	for (i, c) in text.chars().enumerate() {
+		let Some(rows) = glyph(c) else { continue };
+		let left = x + i as u32 * (GLYPH_WIDTH + 1) * scale;
+		for (row, bits) in rows.iter().enumerate() {
+			for column in 0..GLYPH_WIDTH {
+				if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+					continue;
+				}
+				for dy in 0..scale {
+					for dx in 0..scale {
+						let (px, py) = (left + column * scale + dx, y + row as u32 * scale + dy);
+						if px < image.width() && py < image.height() {
+							image.put_pixel(px, py, color);
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::{Luma, GrayImage};
+
+	#[test]
+	fn test_draw_text() {
+		let mut image = GrayImage::new(12, 6);
+		draw_text(&mut image, 1, 1, "1?7", 1, Luma([255]));
+		let lit = |x: u32, y: u32| image.get_pixel(x, y)[0] == 255;
+		// The stem of the 1, nothing for the unknown character, and the 7 two glyphs over.
+		assert!((1..6).all(|y| lit(2, y)));
+		assert!((4..8).all(|x| (0..6).all(|y| !lit(x, y))));
+		assert!((9..12).all(|x| lit(x, 1)));
+		// Clipped at the edge instead of panicking.
+		draw_text(&mut image, 10, 4, "88", 2, Luma([255]));
+	}
+}
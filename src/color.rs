@@ -0,0 +1,147 @@
+// Color space conversions for blending.
+// Images come in gamma encoded sRGB.  Blending those values directly darkens and muddies the midpoints of a dissolve,
+// because 0.5 encoded is only about 21% of the light.  Decode to linear light first, blend, and encode again.
+// OKLab (Björn Ottosson, 2020, https://bottosson.github.io/posts/oklab/) is a perceptual space built on top of
+// linear light, which keeps hue and lightness steadier through the blend between very different colors.
+
+/// The space the two sides of a morph are blended in.  All channels are nominally 0-1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum BlendSpace {
+	/// Blend the encoded values directly, like the prototype.  Fast but dark and muddy in the middle.
+	Srgb,
+	/// Blend physical light intensities.
+	#[default]
+	LinearLight,
+	/// Blend in the perceptual OKLab space.
+	OkLab,
+}
+
+impl BlendSpace {
+	/// Convert an encoded sRGB color, 0-1, into this space.
+	pub fn decode(&self, rgb: [f32; 3]) -> [f32; 3] {
+		match self {
+			BlendSpace::Srgb => rgb,
+			BlendSpace::LinearLight => rgb.map(srgb_to_linear),
+			BlendSpace::OkLab => linear_to_oklab(rgb.map(srgb_to_linear)),
+		}
+	}
+
+	/// Convert a color in this space back to encoded sRGB, 0-1, clamped to the gamut.
+	pub fn encode(&self, color: [f32; 3]) -> [f32; 3] {
+		let rgb = match self {
+			BlendSpace::Srgb => color,
+			BlendSpace::LinearLight => color.map(linear_to_srgb),
+			BlendSpace::OkLab => oklab_to_linear(color).map(linear_to_srgb),
+		};
+		rgb.map(|c| c.clamp(0.0, 1.0))
+	}
+
+	/// Blend two encoded sRGB colors in this space.  Amount 0 is `a` and 1 is `b`.
+	pub fn lerp(&self, a: [f32; 3], b: [f32; 3], amount: f32) -> [f32; 3] {
+		self.encode(lerp3(self.decode(a), self.decode(b), amount))
+	}
+}
+
+pub fn lerp3(a: [f32; 3], b: [f32; 3], amount: f32) -> [f32; 3] {
+	[
+		a[0] + amount*(b[0] - a[0]),
+		a[1] + amount*(b[1] - a[1]),
+		a[2] + amount*(b[2] - a[2]),
+	]
+}
+
+/// Relative luminance (Rec. 709 / sRGB primaries) of an encoded sRGB color, 0-1.
+pub fn luminance(rgb: [f32; 3]) -> f32 {
+	let [r, g, b] = rgb.map(srgb_to_linear);
+	0.2126*r + 0.7152*g + 0.0722*b
+}
+
+/// The sRGB transfer function, encoded 0-1 to linear 0-1.
+pub fn srgb_to_linear(c: f32) -> f32 {
+	if c <= 0.04045 {
+		c / 12.92
+	} else {
+		((c + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+/// The inverse sRGB transfer function, linear 0-1 to encoded 0-1.
+pub fn linear_to_srgb(c: f32) -> f32 {
+	if c <= 0.0031308 {
+		c * 12.92
+	} else {
+		1.055 * c.max(0.0).powf(1.0 / 2.4) - 0.055
+	}
+}
+
+pub fn linear_to_oklab(rgb: [f32; 3]) -> [f32; 3] {
+	let [r, g, b] = rgb;
+	let l = (0.41222146*r + 0.53633255*g + 0.051445995*b).cbrt();
+	let m = (0.2119035*r + 0.6806995*g + 0.10739696*b).cbrt();
+	let s = (0.08830246*r + 0.28171885*g + 0.6299787*b).cbrt();
+	[
+		0.21045426*l + 0.7936178*m - 0.004072047*s,
+		1.9779985*l - 2.4285922*m + 0.4505937*s,
+		0.025904037*l + 0.78277177*m - 0.80867577*s,
+	]
+}
+
+pub fn oklab_to_linear(lab: [f32; 3]) -> [f32; 3] {
+	let [lightness, a, b] = lab;
+	let l = (lightness + 0.39633778*a + 0.21580376*b).powi(3);
+	let m = (lightness - 0.105561346*a - 0.06385417*b).powi(3);
+	let s = (lightness - 0.08948418*a - 1.2914855*b).powi(3);
+	[
+		4.0767417*l - 3.3077116*m + 0.23096994*s,
+		-1.268438*l + 2.6097574*m - 0.34131938*s,
+		-0.0041960863*l - 0.7034186*m + 1.7076147*s,
+	]
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_transfer_round_trip() {
+		for i in 0..=255 {
+			let c = i as f32 / 255.0;
+			assert!((linear_to_srgb(srgb_to_linear(c)) - c).abs() < 1e-5);
+		}
+		assert!((srgb_to_linear(0.5) - 0.2140).abs() < 1e-3);
+	}
+
+	#[test]
+	fn test_oklab_round_trip() {
+		// White is L = 1 with no chroma.
+		let white = linear_to_oklab([1.0, 1.0, 1.0]);
+		assert!((white[0] - 1.0).abs() < 1e-3 && white[1].abs() < 1e-3 && white[2].abs() < 1e-3);
+		for rgb in [[0.2f32, 0.5, 0.9], [1.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.05, 0.8, 0.3]] {
+			let back = oklab_to_linear(linear_to_oklab(rgb));
+			for k in 0..3 {
+				assert!((back[k] - rgb[k]).abs() < 1e-4, "{rgb:?} -> {back:?}");
+			}
+		}
+	}
+
+	#[test]
+	fn test_blend_midpoints() {
+		let black = [0.0f32; 3];
+		let white = [1.0f32; 3];
+		assert!((BlendSpace::Srgb.lerp(black, white, 0.5)[0] - 0.5).abs() < 1e-5);
+		// Half the light is quite a bit brighter than half the encoded value.
+		assert!((BlendSpace::LinearLight.lerp(black, white, 0.5)[0] - 0.7354).abs() < 1e-3);
+		// OKLab lightness 0.5 is perceptual middle gray, which is darker than either.
+		let oklab = BlendSpace::OkLab.lerp(black, white, 0.5);
+		assert!((oklab[0] - 0.3885).abs() < 1e-3 && (oklab[0] - oklab[2]).abs() < 1e-3);
+		// The endpoints come back unchanged in every space.
+		let color = [0.8f32, 0.3, 0.1];
+		for space in [BlendSpace::Srgb, BlendSpace::LinearLight, BlendSpace::OkLab] {
+			let end = space.lerp([0.1, 0.9, 0.4], color, 1.0);
+			for k in 0..3 {
+				assert!((end[k] - color[k]).abs() < 1e-4, "{space:?}");
+			}
+		}
+	}
+}
@@ -0,0 +1,222 @@
+// Matching the colors of the right image to the left before blending.
+// If the two sources were shot under different light, the midframes of a dissolve pulse in exposure and tint.
+// The transfer is fit once per pair of images and the renderer fades it out over the morph, so the right image starts
+// out looking like the left and ends up looking like itself.
+
+use image::DynamicImage;
+use ndarray::prelude::*;
+
+use crate::color::{linear_to_oklab, linear_to_srgb, oklab_to_linear, srgb_to_linear};
+use crate::linalg;
+
+// Fitting doesn't need every pixel of a big image.
+const MAX_SAMPLES: usize = 1 << 18;
+const HISTOGRAM_BINS: usize = 256;
+
+/// How the right image's colors are matched to the left.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum ColorMatch {
+	#[default]
+	None,
+	/// Match the mean and standard deviation of each OKLab channel, after Reinhard et al.  Robust and gentle.
+	MeanVariance,
+	/// Match the full distribution of each sRGB channel.  Strongest, but can posterize or shift hues.
+	Histogram,
+	/// A 3x3 linear light transform plus offset which maps the right image's color covariance onto the left's
+	/// with the least change (the linear Monge-Kantorovich solution).  Handles cross-channel tints.
+	LinearTransform,
+}
+
+/// A fitted color mapping from one image's colors onto another's.  Works on encoded sRGB, 0-1.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorTransfer {
+	MeanVariance { source_mean: [f32; 3], source_deviation: [f32; 3], reference_mean: [f32; 3], reference_deviation: [f32; 3] },
+	/// One lookup table per channel over HISTOGRAM_BINS evenly spaced input values.
+	Histogram { tables: [Vec<f32>; 3] },
+	LinearTransform { matrix: [[f32; 3]; 3], source_mean: [f32; 3], reference_mean: [f32; 3] },
+}
+
+impl ColorTransfer {
+	/// Fit a transfer which makes `source` look like `reference`.  Returns None for ColorMatch::None, or if either
+	/// image has no opaque pixels.  Fully transparent pixels are ignored.
+	pub fn fit(method: ColorMatch, source: &DynamicImage, reference: &DynamicImage) -> Option<Self> {
+		if method == ColorMatch::None {
+			return None;
+		}
+		let source = opaque_samples(source);
+		let reference = opaque_samples(reference);
+		if source.is_empty() || reference.is_empty() {
+			return None;
+		}
+		Some(match method {
+			ColorMatch::None => unreachable!(),
+			ColorMatch::MeanVariance => {
+				let to_lab = |samples: &Vec<[f32; 3]>| samples.iter().map(|c| linear_to_oklab(c.map(srgb_to_linear))).collect::<Vec<_>>();
+				let (source_mean, source_deviation) = mean_and_deviation(&to_lab(&source));
+				let (reference_mean, reference_deviation) = mean_and_deviation(&to_lab(&reference));
+				ColorTransfer::MeanVariance { source_mean, source_deviation, reference_mean, reference_deviation }
+			},
+			ColorMatch::Histogram => {
+				ColorTransfer::Histogram { tables: [0, 1, 2].map(|k| histogram_table(&source, &reference, k)) }
+			},
+			ColorMatch::LinearTransform => {
+				let to_linear = |samples: &Vec<[f32; 3]>| samples.iter().map(|c| c.map(srgb_to_linear)).collect::<Vec<_>>();
+				let (source, reference) = (to_linear(&source), to_linear(&reference));
+				let (source_mean, source_covariance) = mean_and_covariance(&source);
+				let (reference_mean, reference_covariance) = mean_and_covariance(&reference);
+				// T = S^-1/2 (S^1/2 R S^1/2)^1/2 S^-1/2, which satisfies T S T = R.
+				let (root, inverse_root) = symmetric_roots(&source_covariance);
+				let (middle, _) = symmetric_roots(&root.dot(&reference_covariance).dot(&root));
+				let t = inverse_root.dot(&middle).dot(&inverse_root);
+				let matrix = [0, 1, 2].map(|i| [0, 1, 2].map(|j| t[(i, j)]));
+				ColorTransfer::LinearTransform { matrix, source_mean, reference_mean }
+			},
+		})
+	}
+
+	/// Map one encoded sRGB color, 0-1.
+	pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+		let result = match self {
+			ColorTransfer::MeanVariance { source_mean, source_deviation, reference_mean, reference_deviation } => {
+				let lab = linear_to_oklab(rgb.map(srgb_to_linear));
+				let matched = [0, 1, 2].map(|k| {
+					let scale = if source_deviation[k] > 1e-6 { reference_deviation[k] / source_deviation[k] } else { 1.0 };
+					(lab[k] - source_mean[k]) * scale + reference_mean[k]
+				});
+				oklab_to_linear(matched).map(linear_to_srgb)
+			},
+			ColorTransfer::Histogram { tables } => {
+				[0, 1, 2].map(|k| {
+					let position = rgb[k].clamp(0.0, 1.0) * (HISTOGRAM_BINS - 1) as f32;
+					let below = (position.floor() as usize).min(HISTOGRAM_BINS - 2);
+					let t = position - below as f32;
+					tables[k][below] + t*(tables[k][below + 1] - tables[k][below])
+				})
+			},
+			ColorTransfer::LinearTransform { matrix, source_mean, reference_mean } => {
+				let c = rgb.map(srgb_to_linear);
+				let d = [c[0] - source_mean[0], c[1] - source_mean[1], c[2] - source_mean[2]];
+				[0, 1, 2].map(|i| linear_to_srgb(matrix[i][0]*d[0] + matrix[i][1]*d[1] + matrix[i][2]*d[2] + reference_mean[i]))
+			},
+		};
+		result.map(|c| c.clamp(0.0, 1.0))
+	}
+}
+
+/// Encoded sRGB colors of the pixels with any opacity, evenly thinned out to at most MAX_SAMPLES.
+fn opaque_samples(image: &DynamicImage) -> Vec<[f32; 3]> {
+	let rgba = image.to_rgba32f();
+	let step = (rgba.width() as usize * rgba.height() as usize / MAX_SAMPLES).max(1);
+	rgba.pixels().step_by(step).filter(|p| p[3] > 0.0).map(|p| [p[0], p[1], p[2]]).collect()
+}
+
+fn mean_and_deviation(samples: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+	let n = samples.len() as f32;
+	let mean = [0, 1, 2].map(|k| samples.iter().map(|c| c[k]).sum::<f32>() / n);
+	let deviation = [0, 1, 2].map(|k| (samples.iter().map(|c| (c[k] - mean[k]).powi(2)).sum::<f32>() / n).sqrt());
+	(mean, deviation)
+}
+
+fn mean_and_covariance(samples: &Vec<[f32; 3]>) -> ([f32; 3], Array2<f32>) {
+	let n = samples.len() as f32;
+	let mean = [0, 1, 2].map(|k| samples.iter().map(|c| c[k]).sum::<f32>() / n);
+	let mut covariance = Array2::<f32>::zeros((3, 3));
+	for c in samples {
+		for i in 0..3 {
+			for j in 0..3 {
+				covariance[(i, j)] += (c[i] - mean[i]) * (c[j] - mean[j]);
+			}
+		}
+	}
+	(mean, covariance / n)
+}
+
+/// The square root and inverse square root of a symmetric positive semi-definite matrix.
+/// For the inverse, tiny eigenvalues are floored relative to the largest, so a flat or grayscale image (which has
+/// no spread in some direction) doesn't blow it up.
+fn symmetric_roots(a: &Array2<f32>) -> (Array2<f32>, Array2<f32>) {
+	// For a symmetric PSD matrix the SVD is the eigendecomposition, a = u s u^T.
+	let (u, s, _) = linalg::svd(a).expect("SVD of a 3x3 covariance failed.");
+	let floor = (s[0] * 1e-6).max(1e-12);
+	let root = u.dot(&Array2::from_diag(&s.mapv(|v| v.max(0.0).sqrt()))).dot(&u.t());
+	let inverse_root = u.dot(&Array2::from_diag(&s.mapv(|v| 1.0 / v.max(floor).sqrt()))).dot(&u.t());
+	(root, inverse_root)
+}
+
+/// For channel k, the lookup table taking each source value to the reference value at the same quantile.
+fn histogram_table(source: &Vec<[f32; 3]>, reference: &Vec<[f32; 3]>, k: usize) -> Vec<f32> {
+	let cdf = |samples: &Vec<[f32; 3]>| {
+		let mut counts = vec![0usize; HISTOGRAM_BINS];
+		for c in samples {
+			counts[(c[k].clamp(0.0, 1.0) * (HISTOGRAM_BINS - 1) as f32).round() as usize] += 1;
+		}
+		let mut total = 0usize;
+		counts.iter().map(|count| {
+			total += count;
+			total as f32 / samples.len() as f32
+		}).collect::<Vec<f32>>()
+	};
+	let source_cdf = cdf(source);
+	let reference_cdf = cdf(reference);
+	let mut table = Vec::with_capacity(HISTOGRAM_BINS);
+	let mut r = 0;
+	for quantile in source_cdf {
+		// Both CDFs are non-decreasing, so the matching reference bin only ever moves forward.
+		while r + 1 < HISTOGRAM_BINS && reference_cdf[r] < quantile - 1e-6 {
+			r += 1;
+		}
+		table.push(r as f32 / (HISTOGRAM_BINS - 1) as f32);
+	}
+	table
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::{Rgb, RgbImage};
+
+	// A gradient with some variation in every channel, and a darker, blue tinted copy of it.
+	fn image_pair() -> (DynamicImage, DynamicImage) {
+		let reference = RgbImage::from_fn(32, 32, |x, y| Rgb([(x * 6 + 20) as u8, (y * 6 + 30) as u8, ((x + y) * 3 + 10) as u8]));
+		let source = RgbImage::from_fn(32, 32, |x, y| {
+			let p = reference.get_pixel(x, y);
+			Rgb([p[0] / 2, p[1] / 2, (p[2] / 2).saturating_add(60)])
+		});
+		(DynamicImage::ImageRgb8(source), DynamicImage::ImageRgb8(reference))
+	}
+
+	fn mean_error(transfer: &ColorTransfer, source: &DynamicImage, reference: &DynamicImage) -> f32 {
+		let source = source.to_rgb32f();
+		let reference = reference.to_rgb32f();
+		let total: f32 = source.pixels().zip(reference.pixels()).map(|(s, r)| {
+			let matched = transfer.apply(s.0);
+			(0..3).map(|k| (matched[k] - r[k]).abs()).sum::<f32>()
+		}).sum();
+		total / (3 * source.width() * source.height()) as f32
+	}
+
+	#[test]
+	fn test_methods_reduce_difference() {
+		let (source, reference) = image_pair();
+		assert!(ColorTransfer::fit(ColorMatch::None, &source, &reference).is_none());
+		let before = {
+			let (s, r) = (source.to_rgb32f(), reference.to_rgb32f());
+			s.pixels().zip(r.pixels()).map(|(a, b)| (0..3).map(|k| (a[k] - b[k]).abs()).sum::<f32>()).sum::<f32>() / (3 * 32 * 32) as f32
+		};
+		for method in [ColorMatch::MeanVariance, ColorMatch::Histogram, ColorMatch::LinearTransform] {
+			let transfer = ColorTransfer::fit(method, &source, &reference).unwrap();
+			let after = mean_error(&transfer, &source, &reference);
+			assert!(after < 0.25 * before, "{method:?}: {before} -> {after}");
+		}
+	}
+
+	#[test]
+	fn test_matching_itself_is_identity() {
+		let (_, reference) = image_pair();
+		for method in [ColorMatch::MeanVariance, ColorMatch::Histogram, ColorMatch::LinearTransform] {
+			let transfer = ColorTransfer::fit(method, &reference, &reference).unwrap();
+			assert!(mean_error(&transfer, &reference, &reference) < 1e-2, "{method:?}");
+		}
+	}
+}
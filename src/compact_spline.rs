@@ -0,0 +1,328 @@
+// A radial basis spline with compactly supported Wendland kernels, for fits with thousands of control points.
+// The dense ThinPlateSpline builds an (n+3)^2 system and decomposes it, which is fine for a few hundred points
+// but hopeless for dense landmarks.  With a kernel that's exactly zero past some radius, each row of the system
+// only touches the handful of control points nearby.  We store it sparsely and solve with conjugate gradients.
+//
+// Unlike the bordered system in ThinPlateSpline, the affine part is fit first by least squares and the kernels
+// fit the residual.  The kernel matrix alone is symmetric positive definite, which is what CG needs.
+
+use std::collections::HashMap;
+
+use crate::thin_plate_spline::RadialBasis;
+
+const MAX_CG_ITERATIONS: usize = 1000;
+// Relative residual.  Much below 1e-5 is lost in f32 rounding and would just run out the iterations.
+const CG_TOLERANCE: f32 = 1e-5;
+
+pub struct CompactSpline {
+	radius: f32,
+	// [x_weight, y_weight] for each control point.
+	weights: Vec<f32>,
+	// Affine part, destination = offset + (p - centroid) * linear.
+	centroid: (f32, f32),
+	offset: (f32, f32),
+	linear: [f32; 4],
+	grid: SpatialGrid,
+}
+
+impl CompactSpline {
+	/// Given two arrays of points in the form [x, y, x, y, x, y, ...], a regularization alpha, and a support radius
+	/// in the same units as the points, fit the spline.  Points further apart than the radius don't interact at all,
+	/// so it should be large enough to cover a few neighbors of each point.
+	pub fn new(source_points: &Vec<f32>, destination_points: &Vec<f32>, alpha: f32, radius: f32) -> Self {
+		assert_eq!(source_points.len() % 2, 0);
+		assert_eq!(source_points.len(), destination_points.len());
+		assert!(!source_points.is_empty());
+		assert!(radius > 0.0);
+
+		let n_c = source_points.len() / 2;
+		let grid = SpatialGrid::new(source_points, radius);
+		let kernel = RadialBasis::Wendland { radius };
+
+		// Fit the affine part.  Centering makes the intercept decouple from the 2x2 linear part.
+		let mut centroid = (0.0f32, 0.0f32);
+		let mut offset = (0.0f32, 0.0f32);
+		for i in 0..n_c {
+			centroid.0 += source_points[2*i];
+			centroid.1 += source_points[2*i + 1];
+			offset.0 += destination_points[2*i];
+			offset.1 += destination_points[2*i + 1];
+		}
+		centroid = (centroid.0 / n_c as f32, centroid.1 / n_c as f32);
+		offset = (offset.0 / n_c as f32, offset.1 / n_c as f32);
+		let (mut sxx, mut sxy, mut syy) = (0.0f32, 0.0f32, 0.0f32);
+		let (mut tx0, mut tx1, mut ty0, mut ty1) = (0.0f32, 0.0f32, 0.0f32, 0.0f32);
+		for i in 0..n_c {
+			let px = source_points[2*i] - centroid.0;
+			let py = source_points[2*i + 1] - centroid.1;
+			let qx = destination_points[2*i] - offset.0;
+			let qy = destination_points[2*i + 1] - offset.1;
+			sxx += px*px;
+			sxy += px*py;
+			syy += py*py;
+			tx0 += px*qx;
+			tx1 += px*qy;
+			ty0 += py*qx;
+			ty1 += py*qy;
+		}
+		let det = sxx*syy - sxy*sxy;
+		let linear = if det.abs() > 1e-6 * (sxx*syy).max(1e-12) {
+			[
+				(syy*tx0 - sxy*ty0) / det, (syy*tx1 - sxy*ty1) / det,
+				(-sxy*tx0 + sxx*ty0) / det, (-sxy*tx1 + sxx*ty1) / det,
+			]
+		} else {
+			// Collinear or coincident points.  Only a translation is well defined.
+			[1.0, 0.0, 0.0, 1.0]
+		};
+
+		// Assemble the sparse kernel matrix K + alpha I.
+		let mut matrix = SparseMatrix { row_starts: vec![0], columns: vec![], values: vec![] };
+		for i in 0..n_c {
+			let (x, y) = (source_points[2*i], source_points[2*i + 1]);
+			grid.for_each_neighbor(x, y, |j, r| {
+				let mut value = kernel.evaluate(r);
+				if i == j {
+					value += alpha;
+				}
+				if value != 0.0 {
+					matrix.columns.push(j);
+					matrix.values.push(value);
+				}
+			});
+			matrix.row_starts.push(matrix.columns.len());
+		}
+
+		// Residual of the affine fit, then solve K w = residual for each axis.
+		let mut residual_x = vec![0.0f32; n_c];
+		let mut residual_y = vec![0.0f32; n_c];
+		for i in 0..n_c {
+			let (ax, ay) = apply_affine(source_points[2*i], source_points[2*i + 1], centroid, offset, &linear);
+			residual_x[i] = destination_points[2*i] - ax;
+			residual_y[i] = destination_points[2*i + 1] - ay;
+		}
+		let weights_x = conjugate_gradient(&matrix, &residual_x);
+		let weights_y = conjugate_gradient(&matrix, &residual_y);
+		let mut weights = Vec::with_capacity(2*n_c);
+		for i in 0..n_c {
+			weights.push(weights_x[i]);
+			weights.push(weights_y[i]);
+		}
+
+		Self {
+			radius,
+			weights,
+			centroid,
+			offset,
+			linear,
+			grid,
+		}
+	}
+
+	pub fn transform(&self, points: &Vec<f32>) -> Vec<f32> {
+		assert_eq!(points.len() % 2, 0);
+		let kernel = RadialBasis::Wendland { radius: self.radius };
+		let mut result = Vec::with_capacity(points.len());
+		for pt in points.chunks_exact(2) {
+			let (mut x, mut y) = apply_affine(pt[0], pt[1], self.centroid, self.offset, &self.linear);
+			self.grid.for_each_neighbor(pt[0], pt[1], |j, r| {
+				let phi = kernel.evaluate(r);
+				x += phi * self.weights[2*j];
+				y += phi * self.weights[2*j + 1];
+			});
+			result.push(x);
+			result.push(y);
+		}
+		result
+	}
+
+	/// The analytic Jacobian at each point, [dx'/dx, dx'/dy, dy'/dx, dy'/dy] per point.
+	pub fn jacobian(&self, points: &Vec<f32>) -> Vec<f32> {
+		assert_eq!(points.len() % 2, 0);
+		let kernel = RadialBasis::Wendland { radius: self.radius };
+		let mut result = Vec::with_capacity(points.len() * 2);
+		for pt in points.chunks_exact(2) {
+			let mut j = [self.linear[0], self.linear[2], self.linear[1], self.linear[3]];
+			self.grid.for_each_neighbor(pt[0], pt[1], |idx, r| {
+				let g = kernel.derivative_over_r(r);
+				let dx = pt[0] - self.grid.points[2*idx];
+				let dy = pt[1] - self.grid.points[2*idx + 1];
+				j[0] += self.weights[2*idx] * g * dx;
+				j[1] += self.weights[2*idx] * g * dy;
+				j[2] += self.weights[2*idx + 1] * g * dx;
+				j[3] += self.weights[2*idx + 1] * g * dy;
+			});
+			result.extend(j);
+		}
+		result
+	}
+
+	pub fn get_num_control_points(&self) -> usize {
+		self.grid.points.len() / 2
+	}
+}
+
+fn apply_affine(x: f32, y: f32, centroid: (f32, f32), offset: (f32, f32), linear: &[f32; 4]) -> (f32, f32) {
+	let px = x - centroid.0;
+	let py = y - centroid.1;
+	(offset.0 + px*linear[0] + py*linear[2], offset.1 + px*linear[1] + py*linear[3])
+}
+
+/// Buckets points into square cells the size of the support radius,
+/// so everything within the radius of a query is in the 3x3 block of cells around it.
+struct SpatialGrid {
+	cell_size: f32,
+	// The spline's control points, [x, y, x, y, ...].  It keeps the only copy.
+	points: Vec<f32>,
+	cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl SpatialGrid {
+	fn new(points: &[f32], cell_size: f32) -> Self {
+		let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+		for (idx, pt) in points.chunks_exact(2).enumerate() {
+			let key = ((pt[0] / cell_size).floor() as i32, (pt[1] / cell_size).floor() as i32);
+			cells.entry(key).or_default().push(idx);
+		}
+		Self {
+			cell_size,
+			points: points.to_vec(),
+			cells,
+		}
+	}
+
+	/// Call `f(point_index, distance)` for every point strictly within cell_size of (x, y).
+	fn for_each_neighbor<F: FnMut(usize, f32)>(&self, x: f32, y: f32, mut f: F) {
+		let cx = (x / self.cell_size).floor() as i32;
+		let cy = (y / self.cell_size).floor() as i32;
+		for dy in -1..=1 {
+			for dx in -1..=1 {
+				if let Some(bucket) = self.cells.get(&(cx + dx, cy + dy)) {
+					for &idx in bucket {
+						let ddx = self.points[2*idx] - x;
+						let ddy = self.points[2*idx + 1] - y;
+						let r = (ddx*ddx + ddy*ddy).sqrt();
+						if r < self.cell_size {
+							f(idx, r);
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Compressed sparse row storage.
+struct SparseMatrix {
+	row_starts: Vec<usize>,
+	columns: Vec<usize>,
+	values: Vec<f32>,
+}
+
+impl SparseMatrix {
+	fn multiply(&self, x: &[f32], out: &mut [f32]) {
+		for (bounds, out) in self.row_starts.windows(2).zip(out.iter_mut()) {
+			let row = bounds[0]..bounds[1];
+			*out = self.columns[row.clone()].iter().zip(&self.values[row]).map(|(&column, value)| value * x[column]).sum();
+		}
+	}
+
+	/// The diagonal entries, or 1 for rows without one.
+	fn diagonal(&self) -> Vec<f32> {
+		self.row_starts.windows(2).enumerate().map(|(row, bounds)| {
+			let range = bounds[0]..bounds[1];
+			self.columns[range.clone()].iter().zip(&self.values[range]).find(|(&column, _)| column == row).map_or(1.0, |(_, &value)| value)
+		}).collect()
+	}
+}
+
+/// Jacobi-preconditioned conjugate gradients.  The matrix must be symmetric positive definite.
+fn conjugate_gradient(a: &SparseMatrix, b: &[f32]) -> Vec<f32> {
+	let n = b.len();
+	let inverse_diagonal: Vec<f32> = a.diagonal().iter().map(|d| if d.abs() > 1e-12 { 1.0 / d } else { 1.0 }).collect();
+	let b_norm = b.iter().map(|v| v*v).sum::<f32>().sqrt();
+	let mut x = vec![0.0f32; n];
+	if b_norm == 0.0 {
+		return x;
+	}
+
+	let mut r = b.to_vec();
+	let mut z: Vec<f32> = r.iter().zip(&inverse_diagonal).map(|(r, d)| r*d).collect();
+	let mut p = z.clone();
+	let mut ap = vec![0.0f32; n];
+	let mut rz: f32 = r.iter().zip(&z).map(|(r, z)| r*z).sum();
+	let mut r_norm = b_norm;
+
+	for iteration in 0..MAX_CG_ITERATIONS {
+		a.multiply(&p, &mut ap);
+		let p_ap: f32 = p.iter().zip(&ap).map(|(p, ap)| p*ap).sum();
+		if p_ap.abs() < 1e-30 {
+			// The residual check would have stopped an exact solve before this, so the matrix isn't positive definite.
+			log::warn!("The spline solve broke down after {} iterations with a relative residual of {:e}.", iteration, r_norm / b_norm);
+			return x;
+		}
+		let step = rz / p_ap;
+		for i in 0..n {
+			x[i] += step * p[i];
+			r[i] -= step * ap[i];
+		}
+		r_norm = r.iter().map(|v| v*v).sum::<f32>().sqrt();
+		if r_norm <= CG_TOLERANCE * b_norm {
+			return x;
+		}
+		for i in 0..n {
+			z[i] = r[i] * inverse_diagonal[i];
+		}
+		let rz_next: f32 = r.iter().zip(&z).map(|(r, z)| r*z).sum();
+		let beta = rz_next / rz;
+		rz = rz_next;
+		for i in 0..n {
+			p[i] = z[i] + beta * p[i];
+		}
+	}
+	log::warn!("The spline solve stopped after {} iterations with a relative residual of {:e}.", MAX_CG_ITERATIONS, r_norm / b_norm);
+	x
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::prelude::*;
+
+	#[test]
+	fn test_affine_exact() {
+		// An affine map has no residual, so the kernels should contribute nothing at all.
+		let src = vec![0.0f32, 0.0, 10.0, 0.0, 0.0, 10.0, 10.0, 10.0, 3.0, 7.0];
+		let dst: Vec<f32> = src.chunks_exact(2).flat_map(|p| [2.0*p[0] + p[1] + 5.0, p[1] - 3.0]).collect();
+		let spline = CompactSpline::new(&src, &dst, 0.0, 5.0);
+		let out = spline.transform(&vec![5.0, 5.0, -20.0, 40.0]);
+		let expected = vec![20.0f32, 2.0, 5.0, 37.0];
+		for (a, b) in out.iter().zip(expected.iter()) {
+			assert!((a - b).abs() < 1e-3, "{out:?} != {expected:?}");
+		}
+	}
+
+	#[test]
+	fn test_many_points_interpolate() {
+		// A jittered grid of 5,000+ points with random displacements.  A dense solve would be a 5000^2 SVD.
+		let mut rng = StdRng::seed_from_u64(1234);
+		let mut src = vec![];
+		let mut dst = vec![];
+		for y in 0..75 {
+			for x in 0..75 {
+				let px = x as f32 * 10.0 + rng.gen_range(-2.0f32..2.0);
+				let py = y as f32 * 10.0 + rng.gen_range(-2.0f32..2.0);
+				src.push(px);
+				src.push(py);
+				dst.push(px + rng.gen_range(-3.0f32..3.0));
+				dst.push(py + rng.gen_range(-3.0f32..3.0));
+			}
+		}
+		let spline = CompactSpline::new(&src, &dst, 0.0, 25.0);
+		assert_eq!(spline.get_num_control_points(), 75*75);
+		let out = spline.transform(&src);
+		let max_error = out.iter().zip(dst.iter()).map(|(a, b)| (a - b).abs()).fold(0.0f32, f32::max);
+		assert!(max_error < 1e-2, "Max error {max_error}");
+	}
+}
@@ -0,0 +1,155 @@
+// A contact sheet of a morph: evenly spaced frames laid out in a grid on one image, for reviews.
+// Captions are drawn with the built-in pixel font, so this doesn't need a font file.
+
+use anyhow::{bail, Result};
+use image::imageops::{overlay, resize, FilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::ops::RangeInclusive;
+
+use crate::bitmap_font::{draw_text, GLYPH_HEIGHT};
+use crate::export::{blend_amount, render_animation_frame, ExportInput};
+
+/// What to write under each thumbnail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum Caption {
+	None,
+	/// The animation frame number, like "#12".
+	Frame,
+	/// The blend amount, like "0.50".
+	Amount,
+	FrameAndAmount,
+}
+
+impl Caption {
+	pub fn text(&self, frame: u32, amount: f32) -> String {
+		match self {
+			Caption::None => String::new(),
+			Caption::Frame => format!("#{}", frame),
+			Caption::Amount => format!("{:.2}", amount),
+			Caption::FrameAndAmount => format!("#{} {:.2}", frame, amount),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ContactSheetSettings {
+	/// How many frames to show, spread evenly over the range including both ends.
+	pub count: usize,
+	pub columns: usize,
+	/// Thumbnails are scaled to this width, keeping their aspect ratio.
+	pub thumbnail_width: u32,
+	/// Space around and between the thumbnails, in pixels.
+	pub padding: u32,
+	pub background: [u8; 4],
+	pub caption: Caption,
+	pub text_color: [u8; 4],
+	/// Each pixel of the caption font becomes a square this big.
+	pub text_scale: u32,
+}
+
+impl Default for ContactSheetSettings {
+	fn default() -> Self {
+		ContactSheetSettings {
+			count: 9,
+			columns: 3,
+			thumbnail_width: 256,
+			padding: 8,
+			background: [32, 32, 32, 255],
+			caption: Caption::FrameAndAmount,
+			text_color: [255, 255, 255, 255],
+			text_scale: 2,
+		}
+	}
+}
+
+/// `count` frame numbers spread evenly over the range, first and last included.  Short ranges give fewer, without repeats.
+pub fn sample_frames(frames: &RangeInclusive<u32>, count: usize) -> Vec<u32> {
+	let (start, end) = (*frames.start(), *frames.end());
+	if end < start || count == 0 {
+		return vec![];
+	}
+	if count == 1 {
+		return vec![start];
+	}
+	let mut sampled: Vec<u32> = (0..count).map(|i| start + ((end - start) as f64 * i as f64 / (count - 1) as f64).round() as u32).collect();
+	sampled.dedup();
+	sampled
+}
+
+/// Render the sampled frames of the morph and lay them out in a grid, left to right and top to bottom.
+pub fn render_contact_sheet(input: &mut ExportInput, frames: RangeInclusive<u32>, settings: &ContactSheetSettings) -> Result<RgbaImage> {
+	if settings.columns == 0 || settings.thumbnail_width == 0 {
+		bail!("A contact sheet needs at least one column and a thumbnail width.");
+	}
+	let sampled = sample_frames(&frames, settings.count);
+	if sampled.is_empty() {
+		bail!("There are no frames to put on the contact sheet.");
+	}
+
+	let mut thumbnails = vec![];
+	for &frame in &sampled {
+		let amount = blend_amount(frame, &frames);
+		let image = DynamicImage::ImageRgba32F(render_animation_frame(input, frame, amount)?).to_rgba8();
+		let height = ((image.height() as u64 * settings.thumbnail_width as u64) as f64 / image.width() as f64).round().max(1.0) as u32;
+		thumbnails.push((resize(&image, settings.thumbnail_width, height, FilterType::Triangle), settings.caption.text(frame, amount)));
+	}
+
+	let scale = settings.text_scale.max(1);
+	let caption_height = if settings.caption == Caption::None { 0 } else { (GLYPH_HEIGHT + 2) * scale };
+	let cell_width = settings.thumbnail_width;
+	let cell_height = thumbnails.iter().map(|(t, _)| t.height()).max().unwrap() + caption_height;
+	let columns = settings.columns.min(thumbnails.len()) as u32;
+	let rows = (thumbnails.len() as u32).div_ceil(columns);
+	let mut sheet = RgbaImage::from_pixel(
+		columns * cell_width + (columns + 1) * settings.padding,
+		rows * cell_height + (rows + 1) * settings.padding,
+		Rgba(settings.background),
+	);
+	for (i, (thumbnail, caption)) in thumbnails.iter().enumerate() {
+		let (column, row) = (i as u32 % columns, i as u32 / columns);
+		let x = settings.padding + column * (cell_width + settings.padding);
+		let y = settings.padding + row * (cell_height + settings.padding);
+		overlay(&mut sheet, thumbnail, x as i64, y as i64);
+		draw_text(&mut sheet, x, y + thumbnail.height() + scale, caption, scale, Rgba(settings.text_color));
+	}
+	Ok(sheet)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::export::tests::black_to_white;
+	use crate::morph::{Dissolve, MorphSettings};
+
+	#[test]
+	fn test_sample_frames() {
+		assert_eq!(sample_frames(&(0..=8), 5), vec![0, 2, 4, 6, 8]);
+		assert_eq!(sample_frames(&(10..=20), 3), vec![10, 15, 20]);
+		assert_eq!(sample_frames(&(0..=2), 9), vec![0, 1, 2]);
+		assert_eq!(sample_frames(&(4..=9), 1), vec![4]);
+		assert_eq!(Caption::FrameAndAmount.text(12, 0.5), "#12 0.50");
+	}
+
+	#[test]
+	fn test_contact_sheet() {
+		let (animation, mut left, mut right) = black_to_white();
+		let settings = ContactSheetSettings { count: 5, columns: 2, thumbnail_width: 8, padding: 2, text_scale: 1, ..Default::default() };
+		let mut input = ExportInput { animation: &animation, left: &mut left, right: &mut right, morph_settings: &MorphSettings::default(), dissolve: &Dissolve::Uniform };
+		let sheet = render_contact_sheet(&mut input, 0..=4, &settings).unwrap();
+		// Two columns of 8 wide thumbnails, three rows of 6 tall thumbnails with 7 pixel captions.
+		assert_eq!(sheet.dimensions(), (2 * 8 + 3 * 2, 3 * 13 + 4 * 2));
+		let background = Rgba(settings.background);
+		assert_eq!(*sheet.get_pixel(0, 0), background);
+		// The first thumbnail is black, the last white, and the unused last cell is empty.
+		assert_eq!(sheet.get_pixel(4, 4).0, [0, 0, 0, 255]);
+		assert_eq!(sheet.get_pixel(4, 2 + 2 * 15 + 2).0, [255, 255, 255, 255]);
+		assert_eq!(*sheet.get_pixel(14, 2 + 2 * 15 + 2), background);
+		// "#0 0.00" under the first thumbnail.  The left column of '#' is on every other row.
+		let text = Rgba(settings.text_color);
+		assert_eq!(*sheet.get_pixel(2, 2 + 6 + 1), text);
+		assert_eq!(*sheet.get_pixel(2, 2 + 6 + 2), text);
+		assert_eq!(*sheet.get_pixel(3, 2 + 6 + 2), text);
+		assert_eq!(*sheet.get_pixel(3, 2 + 6 + 1), background);
+	}
+}
@@ -0,0 +1,262 @@
+// Diagnostic drawings over a rendered frame, so a bad warp can be shown and reviewed from one image.
+// Everything is drawn in output space: the morph points, the left image's pixel grid carried through the warp,
+// the Delaunay mesh of the morph points, where each point travels from left to right, and where the warp folds over.
+
+use image::{Rgba, Rgba32FImage};
+use imageproc::drawing::{draw_filled_circle_mut, draw_line_segment_mut};
+
+use crate::animation_system::Animation;
+use crate::annotation::{warp_polyline, Direction};
+use crate::bitmap_font::draw_text;
+use crate::morph::interpolate_points;
+use crate::warp::{JacobianMap, WarpMethod};
+
+// Colors are encoded sRGB, 0-1, and picked to stand out from each other rather than look nice.
+const POINT_COLOR: [f32; 4] = [1.0, 0.85, 0.0, 1.0];
+const GRID_COLOR: [f32; 4] = [0.0, 0.8, 1.0, 1.0];
+const MESH_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+const DISPLACEMENT_COLOR: [f32; 4] = [1.0, 0.2, 1.0, 1.0];
+const FOLD_OVER_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 0.5];
+// Samples the fold over check takes, in pixels.
+const FOLD_OVER_STEP: u32 = 2;
+
+/// Which overlays to draw.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct OverlaySettings {
+	pub points: bool,
+	/// Channel indices next to the points.
+	pub labels: bool,
+	/// Grid line spacing, in left image pixels.  None for no grid.
+	pub grid_spacing: Option<u32>,
+	/// The Delaunay triangulation of the morph points.
+	pub triangles: bool,
+	/// A line from each left point to its right point.
+	pub displacement: bool,
+	/// Tint where the warp from the output to either image folds over.
+	pub fold_over: bool,
+	pub point_radius: u32,
+	/// Each pixel of the label font becomes a square this big.
+	pub label_scale: u32,
+}
+
+impl Default for OverlaySettings {
+	fn default() -> Self {
+		OverlaySettings {
+			points: true,
+			labels: true,
+			grid_spacing: Some(32),
+			triangles: false,
+			displacement: false,
+			fold_over: true,
+			point_radius: 3,
+			label_scale: 2,
+		}
+	}
+}
+
+/// Draw the overlays on a frame rendered at `morph_points`, with the morph warps fit by `warp`.
+/// The grid is the left image's, so `image` should be the left image's size, as the exporters render.
+pub fn draw_overlay(
+	image: &mut Rgba32FImage,
+	left_points: &Vec<f32>,
+	right_points: &Vec<f32>,
+	morph_points: &Vec<f32>,
+	warp: &WarpMethod,
+	settings: &OverlaySettings,
+) {
+	let (width, height) = image.dimensions();
+	if settings.fold_over && left_points.len() >= 6 {
+		for source_points in [left_points, right_points] {
+			// The renderer pulls pixels backward from the output, so that's the direction which has to stay untangled.
+			let backward = warp.fit(morph_points, source_points);
+			if let Some(map) = JacobianMap::new(backward.as_ref(), width, height, FOLD_OVER_STEP) {
+				if map.has_fold_over() {
+					for (x, y, pixel) in image.enumerate_pixels_mut() {
+						if map.get_determinant(x, y) <= 0.0 {
+							tint(pixel, FOLD_OVER_COLOR);
+						}
+					}
+				}
+			}
+		}
+	}
+	if let Some(spacing) = settings.grid_spacing.filter(|s| *s > 0) {
+		if left_points.len() >= 6 {
+			let forward = warp.fit(left_points, morph_points);
+			let (right, bottom) = ((width.max(1) - 1) as f32, (height.max(1) - 1) as f32);
+			let mut lines = vec![];
+			lines.extend((0..width).step_by(spacing as usize).map(|x| vec![x as f32, 0.0, x as f32, bottom]));
+			lines.extend((0..height).step_by(spacing as usize).map(|y| vec![0.0, y as f32, right, y as f32]));
+			for line in lines {
+				// Short segments so the bends of the warp show.
+				let warped = warp_polyline(forward.as_ref(), &line, false, (spacing as f32 / 4.0).max(1.0), Direction::Forward).unwrap();
+				for segment in warped.windows(4).step_by(2) {
+					draw_line_segment_mut(image, (segment[0], segment[1]), (segment[2], segment[3]), Rgba(GRID_COLOR));
+				}
+			}
+		}
+	}
+	if settings.triangles {
+		for triangle in delaunay(morph_points) {
+			for k in 0..3 {
+				let (a, b) = (triangle[k], triangle[(k + 1) % 3]);
+				draw_line_segment_mut(image, point(morph_points, a), point(morph_points, b), Rgba(MESH_COLOR));
+			}
+		}
+	}
+	if settings.displacement {
+		for i in 0..left_points.len() / 2 {
+			let (start, end) = (point(left_points, i), point(right_points, i));
+			draw_line_segment_mut(image, start, end, Rgba(DISPLACEMENT_COLOR));
+			draw_filled_circle_mut(image, (end.0.round() as i32, end.1.round() as i32), 1, Rgba(DISPLACEMENT_COLOR));
+		}
+	}
+	let scale = settings.label_scale.max(1);
+	for i in 0..morph_points.len() / 2 {
+		let (x, y) = point(morph_points, i);
+		if settings.points {
+			draw_filled_circle_mut(image, (x.round() as i32, y.round() as i32), settings.point_radius as i32, Rgba(POINT_COLOR));
+		}
+		// Labels start below and right of their point, so there's nothing to draw for points outside the image.
+		if settings.labels && x >= 0.0 && y >= 0.0 && x < width as f32 && y < height as f32 {
+			let offset = settings.point_radius.saturating_add(scale);
+			draw_text(image, (x.round() as u32).saturating_add(offset), (y.round() as u32).saturating_add(offset), &i.to_string(), scale, Rgba(POINT_COLOR));
+		}
+	}
+}
+
+/// Draw the overlays for one frame of an animation, with the same points and blend amount the exporters use.
+pub fn draw_animation_overlay(image: &mut Rgba32FImage, animation: &Animation, frame: u32, amount: f32, warp: &WarpMethod, settings: &OverlaySettings) {
+	let (left_points, right_points) = animation.get_points(frame);
+	let morph_points = interpolate_points(&left_points, &right_points, amount);
+	draw_overlay(image, &left_points, &right_points, &morph_points, warp, settings);
+}
+
+/// The Delaunay triangulation of [x, y, ...] points, as triples of point indices, all wound the same way (clockwise
+/// on screen, since y points down).  Bowyer-Watson, which is quadratic but fine for the few hundred points of a morph.
+/// Repeated points are left out of the mesh.
+pub fn delaunay(points: &[f32]) -> Vec<[usize; 3]> {
+	let n = points.len() / 2;
+	if n < 3 {
+		return vec![];
+	}
+	let mut vertices: Vec<(f64, f64)> = points.chunks_exact(2).map(|p| (p[0] as f64, p[1] as f64)).collect();
+	// A triangle big enough to hold everything, removed again at the end.
+	let (mut min_x, mut min_y, mut max_x, mut max_y) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+	for &(x, y) in &vertices {
+		min_x = min_x.min(x);
+		min_y = min_y.min(y);
+		max_x = max_x.max(x);
+		max_y = max_y.max(y);
+	}
+	let size = (max_x - min_x).max(max_y - min_y).max(1.0) * 20.0;
+	let (center_x, center_y) = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+	vertices.extend([(center_x - size, center_y - size), (center_x + size, center_y - size), (center_x, center_y + size)]);
+
+	let mut triangles: Vec<[usize; 3]> = vec![[n, n + 1, n + 2]];
+	for i in 0..n {
+		if vertices[..i].contains(&vertices[i]) {
+			continue;
+		}
+		let (bad, good): (Vec<[usize; 3]>, Vec<[usize; 3]>) = triangles.into_iter().partition(|t| in_circumcircle(&vertices, t, vertices[i]));
+		triangles = good;
+		// The boundary of the hole is every edge of the removed triangles which only one of them has.
+		let edges: Vec<(usize, usize)> = bad.iter().flat_map(|t| [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])]).collect();
+		for &(a, b) in &edges {
+			if !edges.contains(&(b, a)) {
+				triangles.push(oriented(&vertices, [a, b, i]));
+			}
+		}
+	}
+	triangles.retain(|t| t.iter().all(|&v| v < n));
+	triangles
+}
+
+fn cross(vertices: &[(f64, f64)], t: &[usize; 3]) -> f64 {
+	let (a, b, c) = (vertices[t[0]], vertices[t[1]], vertices[t[2]]);
+	(b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+fn oriented(vertices: &[(f64, f64)], t: [usize; 3]) -> [usize; 3] {
+	if cross(vertices, &t) < 0.0 { [t[0], t[2], t[1]] } else { t }
+}
+
+fn in_circumcircle(vertices: &[(f64, f64)], t: &[usize; 3], p: (f64, f64)) -> bool {
+	let [a, b, c] = t.map(|v| (vertices[v].0 - p.0, vertices[v].1 - p.1));
+	let determinant = (a.0*a.0 + a.1*a.1) * (b.0*c.1 - c.0*b.1)
+		- (b.0*b.0 + b.1*b.1) * (a.0*c.1 - c.0*a.1)
+		+ (c.0*c.0 + c.1*c.1) * (a.0*b.1 - b.0*a.1);
+	// The sign flips with the winding of the triangle.
+	determinant * cross(vertices, t).signum() > 0.0
+}
+
+fn point(points: &[f32], idx: usize) -> (f32, f32) {
+	(points[2 * idx], points[2 * idx + 1])
+}
+
+fn tint(pixel: &mut Rgba<f32>, color: [f32; 4]) {
+	for k in 0..3 {
+		pixel[k] += color[3] * (color[k] - pixel[k]);
+	}
+	pixel[3] = pixel[3].max(color[3]);
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::thin_plate_spline::RadialBasis;
+
+	#[test]
+	fn test_delaunay() {
+		// A square with a point in the middle makes a fan of four.
+		let points = vec![0.0, 0.0, 10.0, 0.0, 10.0, 10.0, 0.0, 10.0, 5.0, 5.0];
+		let mut triangles = delaunay(&points);
+		assert_eq!(triangles.len(), 4);
+		assert!(triangles.iter().all(|t| t.contains(&4)));
+		// A skinny quad gets split along its short diagonal.
+		let points = vec![0.0, 0.0, 10.0, -1.0, 20.0, 0.0, 10.0, 1.0];
+		triangles = delaunay(&points);
+		assert_eq!(triangles.len(), 2);
+		assert!(triangles.iter().all(|t| t.contains(&1) && t.contains(&3)));
+		let vertices: Vec<(f64, f64)> = points.chunks_exact(2).map(|p| (p[0] as f64, p[1] as f64)).collect();
+		assert!(triangles.iter().all(|t| cross(&vertices, t) > 0.0));
+		// Duplicates and degenerate input don't break it.
+		assert_eq!(delaunay(&[0.0, 0.0, 0.0, 0.0, 5.0, 0.0, 0.0, 5.0]).len(), 1);
+		assert!(delaunay(&[0.0, 0.0, 1.0, 1.0]).is_empty());
+	}
+
+	#[test]
+	fn test_overlay() {
+		let corners = vec![0.0, 0.0, 31.0, 0.0, 0.0, 31.0, 31.0, 31.0];
+		let mut left_points = corners.clone();
+		left_points.extend([10.0, 16.0, 20.0, 16.0]);
+		let background = Rgba([0.0, 0.0, 0.0, 1.0]);
+		let settings = OverlaySettings { grid_spacing: Some(8), triangles: true, displacement: true, label_scale: 1, ..Default::default() };
+		// A fixed alpha, since cross-validation would smooth the crossed points below away as noise.
+		let warp = WarpMethod::ThinPlateSpline { alpha: Some(0.0), kernel: RadialBasis::ThinPlate };
+
+		let mut image = Rgba32FImage::from_pixel(32, 32, background);
+		draw_overlay(&mut image, &left_points, &left_points, &left_points, &warp, &settings);
+		// Nothing moves, so the grid is straight, there's no fold over, and the points are drawn last, on top.
+		assert_eq!(image.get_pixel(8, 3).0, GRID_COLOR);
+		assert_eq!(image.get_pixel(10, 16).0, POINT_COLOR);
+		assert_eq!(*image.get_pixel(5, 5), background);
+
+		// Swapping the two inner points on the right side folds the warp between them.
+		let mut right_points = corners.clone();
+		right_points.extend([20.0, 16.0, 10.0, 16.0]);
+		let mut image = Rgba32FImage::from_pixel(32, 32, background);
+		let settings = OverlaySettings { points: false, labels: false, grid_spacing: None, ..settings };
+		draw_overlay(&mut image, &left_points, &right_points, &left_points, &warp, &settings);
+		assert_eq!(image.get_pixel(15, 13).0, [0.5, 0.0, 0.0, 1.0]);
+		// The displacement lines run between the swapped points.
+		assert_eq!(image.get_pixel(15, 16).0, DISPLACEMENT_COLOR);
+
+		// A point far off the image has no label to draw.
+		let mut far = corners.clone();
+		far.extend([u32::MAX as f32, 16.0]);
+		let labels_only = OverlaySettings { points: false, labels: true, triangles: false, displacement: false, fold_over: false, ..settings };
+		draw_overlay(&mut image, &far, &far, &far, &warp, &labels_only);
+	}
+}
@@ -0,0 +1,262 @@
+// Rendering an animation out to files, the Rust side of the prototype's export() loop.
+
+use anyhow::{bail, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat, Rgba32FImage};
+use std::fs::File;
+use std::io::BufWriter;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::animation_system::Animation;
+use crate::debug_overlay::{draw_animation_overlay, OverlaySettings};
+use crate::image_source::FrameProvider;
+use crate::morph::{interpolate_points, render_frame, Dissolve, MorphInput, MorphSettings};
+
+/// The placeholder in a filename pattern which is replaced with the frame number.
+pub const FRAME_PLACEHOLDER: &str = "{frame}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum SequenceFormat {
+	Png,
+	/// Quality is 1-100.  JPEG has no alpha, so transparent areas come out black.
+	Jpeg { quality: u8 },
+	Tiff,
+	/// Lossless.
+	WebP,
+}
+
+impl SequenceFormat {
+	pub fn extension(&self) -> &'static str {
+		match self {
+			SequenceFormat::Png => "png",
+			SequenceFormat::Jpeg { .. } => "jpg",
+			SequenceFormat::Tiff => "tif",
+			SequenceFormat::WebP => "webp",
+		}
+	}
+
+	pub fn supports_bit_depth(&self, bit_depth: BitDepth) -> bool {
+		match self {
+			SequenceFormat::Png | SequenceFormat::Tiff => true,
+			SequenceFormat::Jpeg { .. } | SequenceFormat::WebP => bit_depth == BitDepth::Eight,
+		}
+	}
+}
+
+/// Bits per channel of the written images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum BitDepth {
+	Eight,
+	/// Smoother gradients for grading later.  PNG and TIFF only.
+	Sixteen,
+}
+
+/// What to do when a file with the same name is already there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum ExistingFiles {
+	Overwrite,
+	/// Leave it and don't render that frame.  Handy for resuming an interrupted export.
+	Skip,
+	/// Stop with an error before writing anything.
+	Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SequenceSettings {
+	pub directory: PathBuf,
+	/// The file name without extension.  Must contain FRAME_PLACEHOLDER, e.g. "morph_{frame}".
+	pub pattern: String,
+	/// Zero pad frame numbers to at least this many digits.
+	pub padding: usize,
+	pub format: SequenceFormat,
+	pub bit_depth: BitDepth,
+	pub existing: ExistingFiles,
+	/// Draw diagnostics over every frame.
+	pub overlay: Option<OverlaySettings>,
+}
+
+impl Default for SequenceSettings {
+	fn default() -> Self {
+		SequenceSettings {
+			directory: PathBuf::from("."),
+			pattern: FRAME_PLACEHOLDER.to_string(),
+			padding: 4,
+			format: SequenceFormat::Png,
+			bit_depth: BitDepth::Eight,
+			existing: ExistingFiles::Overwrite,
+			overlay: None,
+		}
+	}
+}
+
+impl SequenceSettings {
+	/// The path a given frame number is written to.
+	pub fn get_path(&self, frame: u32) -> PathBuf {
+		let number = format!("{:0width$}", frame, width = self.padding);
+		let name = self.pattern.replace(FRAME_PLACEHOLDER, &number);
+		self.directory.join(format!("{}.{}", name, self.format.extension()))
+	}
+}
+
+/// The blend amount of a frame within an export range, 0 at the first frame and 1 at the last.
+pub fn blend_amount(frame: u32, frames: &RangeInclusive<u32>) -> f32 {
+	let (start, end) = (*frames.start(), *frames.end());
+	if end <= start {
+		return 0.0;
+	}
+	(frame.saturating_sub(start) as f32 / (end - start) as f32).clamp(0.0, 1.0)
+}
+
+/// What the exporters render from: the animation, the frames on either side and how to morph between them.
+pub struct ExportInput<'a> {
+	pub animation: &'a Animation,
+	pub left: &'a mut dyn FrameProvider,
+	pub right: &'a mut dyn FrameProvider,
+	pub morph_settings: &'a MorphSettings,
+	pub dissolve: &'a Dissolve,
+}
+
+/// Render one frame of an animation at the size of the left image.
+/// The points are taken from the animation at `frame`, and the morph points and pixel blend both follow `amount`.
+pub fn render_animation_frame(input: &mut ExportInput, frame: u32, amount: f32) -> Result<Rgba32FImage> {
+	let animation = input.animation;
+	if animation.get_num_channels() < 3 {
+		bail!("At least three points are needed to morph, but the animation has {}.", animation.get_num_channels());
+	}
+	let (left_points, right_points) = animation.get_points(frame);
+	let morph_points = interpolate_points(&left_points, &right_points, amount);
+	let left_image = input.left.get_frame(frame);
+	let right_image = input.right.get_frame(frame);
+	let morph_input = MorphInput { dissolve: input.dissolve, ..MorphInput::new(&left_points, &right_points, left_image, right_image, &morph_points) };
+	Ok(render_frame(&morph_input, amount, input.morph_settings))
+}
+
+/// Render every frame in the range to an image sequence, blending from left at the first frame to right at the last.
+/// Files are numbered by animation frame.  Returns the paths which were written, which leaves out skipped frames.
+pub fn export_sequence(input: &mut ExportInput, frames: RangeInclusive<u32>, settings: &SequenceSettings) -> Result<Vec<PathBuf>> {
+	if !settings.pattern.contains(FRAME_PLACEHOLDER) {
+		bail!("The filename pattern '{}' needs a {} placeholder or every frame would overwrite the last.", settings.pattern, FRAME_PLACEHOLDER);
+	}
+	if !settings.format.supports_bit_depth(settings.bit_depth) {
+		bail!("{:?} can't be written at {:?} bits per channel.", settings.format, settings.bit_depth);
+	}
+	if settings.existing == ExistingFiles::Fail {
+		if let Some(path) = frames.clone().map(|f| settings.get_path(f)).find(|p| p.exists()) {
+			bail!("{} already exists.", path.display());
+		}
+	}
+	std::fs::create_dir_all(&settings.directory)?;
+
+	let mut written = vec![];
+	for frame in frames.clone() {
+		let path = settings.get_path(frame);
+		if settings.existing == ExistingFiles::Skip && path.exists() {
+			continue;
+		}
+		let amount = blend_amount(frame, &frames);
+		let mut image = render_animation_frame(input, frame, amount)?;
+		if let Some(overlay) = &settings.overlay {
+			draw_animation_overlay(&mut image, input.animation, frame, amount, &input.morph_settings.warp, overlay);
+		}
+		save_image(image, &path, settings.format, settings.bit_depth)?;
+		written.push(path);
+	}
+	Ok(written)
+}
+
+fn save_image(image: Rgba32FImage, path: &Path, format: SequenceFormat, bit_depth: BitDepth) -> Result<()> {
+	let image = DynamicImage::ImageRgba32F(image);
+	match (format, bit_depth) {
+		(SequenceFormat::Jpeg { quality }, _) => {
+			let mut encoder = JpegEncoder::new_with_quality(BufWriter::new(File::create(path)?), quality.clamp(1, 100));
+			encoder.encode_image(&image.to_rgb8())?;
+		},
+		(SequenceFormat::Png, BitDepth::Eight) => image.to_rgba8().save_with_format(path, ImageFormat::Png)?,
+		(SequenceFormat::Png, BitDepth::Sixteen) => image.to_rgba16().save_with_format(path, ImageFormat::Png)?,
+		(SequenceFormat::Tiff, BitDepth::Eight) => image.to_rgba8().save_with_format(path, ImageFormat::Tiff)?,
+		(SequenceFormat::Tiff, BitDepth::Sixteen) => image.to_rgba16().save_with_format(path, ImageFormat::Tiff)?,
+		(SequenceFormat::WebP, _) => image.to_rgba8().save_with_format(path, ImageFormat::WebP)?,
+	}
+	Ok(())
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use crate::image_source::StaticImageProvider;
+	use image::{Rgb, RgbImage};
+
+	/// A fresh, empty directory under the system temp directory.
+	pub(crate) fn scratch_directory(name: &str) -> PathBuf {
+		let directory = std::env::temp_dir().join(format!("morph_tool_{}_{}", name, std::process::id()));
+		let _ = std::fs::remove_dir_all(&directory);
+		directory
+	}
+
+	/// A black to white morph over frames 0-4, with a point animating along the way.
+	pub(crate) fn black_to_white() -> (Animation, StaticImageProvider, StaticImageProvider) {
+		let mut animation = Animation::new();
+		for (x, y) in [(0.0, 0.0), (15.0, 0.0), (0.0, 11.0), (15.0, 11.0)] {
+			animation.set_point(x, y, x, y, 0, None);
+		}
+		animation.set_point(8.0, 6.0, 8.0, 6.0, 0, None);
+		animation.set_point(8.0, 6.0, 9.0, 5.0, 4, Some(4));
+		let left = StaticImageProvider::new(DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 12, Rgb([0, 0, 0]))));
+		let right = StaticImageProvider::new(DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 12, Rgb([255, 255, 255]))));
+		(animation, left, right)
+	}
+
+	#[test]
+	fn test_paths() {
+		let settings = SequenceSettings { directory: PathBuf::from("out"), pattern: "shot_{frame}_v2".to_string(), padding: 3, ..Default::default() };
+		assert_eq!(settings.get_path(7), PathBuf::from("out/shot_007_v2.png"));
+		assert_eq!(settings.get_path(12345), PathBuf::from("out/shot_12345_v2.png"));
+		assert_eq!(blend_amount(10, &(10..=20)), 0.0);
+		assert_eq!(blend_amount(15, &(10..=20)), 0.5);
+		assert_eq!(blend_amount(20, &(10..=20)), 1.0);
+		assert_eq!(blend_amount(3, &(3..=3)), 0.0);
+	}
+
+	#[test]
+	fn test_export_sequence() {
+		let directory = scratch_directory("sequence");
+		let (animation, mut left, mut right) = black_to_white();
+		let settings = SequenceSettings { directory: directory.clone(), pattern: "m{frame}".to_string(), padding: 2, ..Default::default() };
+		let mut input = ExportInput { animation: &animation, left: &mut left, right: &mut right, morph_settings: &MorphSettings::default(), dissolve: &Dissolve::Uniform };
+		let written = export_sequence(&mut input, 0..=4, &settings).unwrap();
+		assert_eq!(written.len(), 5);
+		let first = image::open(directory.join("m00.png")).unwrap().to_rgba8();
+		let last = image::open(directory.join("m04.png")).unwrap().to_rgba8();
+		assert_eq!(first.dimensions(), (16, 12));
+		assert_eq!(first.get_pixel(3, 3).0, [0, 0, 0, 255]);
+		assert_eq!(last.get_pixel(3, 3).0, [255, 255, 255, 255]);
+
+		// Skip leaves existing frames alone, Fail refuses to start.
+		std::fs::remove_file(directory.join("m02.png")).unwrap();
+		let skip = SequenceSettings { existing: ExistingFiles::Skip, ..settings.clone() };
+		let written = export_sequence(&mut input, 0..=4, &skip).unwrap();
+		assert_eq!(written, vec![directory.join("m02.png")]);
+		let fail = SequenceSettings { existing: ExistingFiles::Fail, ..settings.clone() };
+		assert!(export_sequence(&mut input, 0..=4, &fail).is_err());
+
+		// Other formats and depths.
+		let deep = SequenceSettings { bit_depth: BitDepth::Sixteen, format: SequenceFormat::Tiff, ..settings.clone() };
+		export_sequence(&mut input, 2..=2, &deep).unwrap();
+		assert!(matches!(image::open(directory.join("m02.tif")).unwrap(), DynamicImage::ImageRgba16(_)));
+		let jpeg = SequenceSettings { format: SequenceFormat::Jpeg { quality: 90 }, ..settings.clone() };
+		export_sequence(&mut input, 2..=2, &jpeg).unwrap();
+		assert_eq!(image::open(directory.join("m02.jpg")).unwrap().width(), 16);
+		let webp = SequenceSettings { format: SequenceFormat::WebP, ..settings.clone() };
+		export_sequence(&mut input, 2..=2, &webp).unwrap();
+		assert!(directory.join("m02.webp").exists());
+
+		// Bad settings fail up front.
+		let deep_jpeg = SequenceSettings { bit_depth: BitDepth::Sixteen, ..jpeg };
+		assert!(export_sequence(&mut input, 0..=4, &deep_jpeg).is_err());
+		let no_placeholder = SequenceSettings { pattern: "frame".to_string(), ..settings };
+		assert!(export_sequence(&mut input, 0..=4, &no_placeholder).is_err());
+		std::fs::remove_dir_all(&directory).unwrap();
+	}
+}
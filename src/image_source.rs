@@ -48,6 +48,12 @@ pub struct StaticImageProvider {
 }
 
 impl StaticImageProvider {
+	pub fn new(img: DynamicImage) -> Self {
+		Self {
+			img
+		}
+	}
+
 	pub fn new_from_file<P: AsRef<Path>>(filename: P) -> Result<Self> {
 		let img = image::open(filename.as_ref())?;
 		Ok(Self {
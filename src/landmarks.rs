@@ -0,0 +1,567 @@
+// Reading landmarks made by other annotation tools, so points don't all have to be placed by hand.
+// Supported are ibug/dlib .pts files, CSV, LabelMe JSON and COCO keypoint JSON.  A left and a right file are paired up
+// by position or by label, and each pair becomes a new channel of the animation.
+// Going the other way, the points of an animation can be written out as CSV, .pts, COCO or a keyframe JSON.
+// The keyframe JSON is also what the Python prototype saves, so its projects can be read back in as an Animation.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::animation_system::Animation;
+
+/// One annotated point.  The label is whatever name the file gives it, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Landmark {
+	pub label: Option<String>,
+	pub x: f32,
+	pub y: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum LandmarkFormat {
+	/// ibug/dlib: a small header, then one "x y" per line between braces.  No labels.
+	Pts,
+	/// One "x,y" or "label,x,y" per line.  Lines which don't parse, like a header, are skipped.
+	Csv,
+	/// Point shapes from a LabelMe file, labeled by shape label.
+	LabelMe,
+	/// The keypoints of one annotation, by its position in the file, labeled by the category's keypoint names.
+	/// Keypoints with visibility 0 weren't placed and are left out.
+	Coco { annotation: usize },
+}
+
+impl LandmarkFormat {
+	/// Guess the format from a file's extension and, for JSON, its contents.
+	pub fn detect(path: &Path, contents: &str) -> Option<Self> {
+		match path.extension()?.to_str()?.to_lowercase().as_str() {
+			"pts" => Some(LandmarkFormat::Pts),
+			"csv" | "txt" => Some(LandmarkFormat::Csv),
+			"json" => {
+				let value: serde_json::Value = serde_json::from_str(contents).ok()?;
+				if value.get("shapes").is_some() {
+					Some(LandmarkFormat::LabelMe)
+				} else if value.get("annotations").is_some() {
+					Some(LandmarkFormat::Coco { annotation: 0 })
+				} else {
+					None
+				}
+			},
+			_ => None,
+		}
+	}
+}
+
+/// How the points of the left file are paired with the points of the right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum Matching {
+	/// The nth point of one with the nth of the other.  Both need the same number of points.
+	Index,
+	/// Points with the same label, in the order of the left file.  Labels only on one side are left out.
+	Label,
+}
+
+// The parts of the JSON formats that matter here.  Everything else in the files is ignored.
+#[derive(serde::Deserialize)]
+struct LabelMeFile {
+	shapes: Vec<LabelMeShape>,
+}
+
+#[derive(serde::Deserialize)]
+struct LabelMeShape {
+	label: String,
+	points: Vec<[f32; 2]>,
+	#[serde(default)]
+	shape_type: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct CocoFile {
+	#[serde(default)]
+	categories: Vec<CocoCategory>,
+	annotations: Vec<CocoAnnotation>,
+}
+
+#[derive(serde::Deserialize)]
+struct CocoCategory {
+	id: u64,
+	#[serde(default)]
+	keypoints: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct CocoAnnotation {
+	#[serde(default)]
+	category_id: Option<u64>,
+	keypoints: Vec<f32>,
+}
+
+/// Parse landmarks from the text of a file.
+pub fn parse_landmarks(contents: &str, format: LandmarkFormat) -> Result<Vec<Landmark>> {
+	match format {
+		LandmarkFormat::Pts => parse_pts(contents),
+		LandmarkFormat::Csv => parse_csv(contents),
+		LandmarkFormat::LabelMe => {
+			let file: LabelMeFile = serde_json::from_str(contents)?;
+			// Missing shape types are points in old LabelMe versions.
+			Ok(file.shapes.into_iter()
+				.filter(|s| s.shape_type.as_deref().unwrap_or("point") == "point")
+				.filter_map(|s| s.points.first().map(|p| Landmark { label: Some(s.label.clone()), x: p[0], y: p[1] }))
+				.collect())
+		},
+		LandmarkFormat::Coco { annotation } => {
+			let file: CocoFile = serde_json::from_str(contents)?;
+			let entry = file.annotations.get(annotation)
+				.ok_or_else(|| anyhow!("There is no annotation {} among the {} in the file.", annotation, file.annotations.len()))?;
+			if entry.keypoints.len() % 3 != 0 {
+				bail!("COCO keypoints come in x, y, visibility triples, but annotation {} has {} values.", annotation, entry.keypoints.len());
+			}
+			let names = file.categories.iter().find(|c| Some(c.id) == entry.category_id).map(|c| c.keypoints.clone()).unwrap_or_default();
+			Ok(entry.keypoints.chunks_exact(3).enumerate()
+				.filter(|(_, k)| k[2] > 0.0)
+				.map(|(i, k)| Landmark { label: names.get(i).cloned(), x: k[0], y: k[1] })
+				.collect())
+		},
+	}
+}
+
+fn parse_pts(contents: &str) -> Result<Vec<Landmark>> {
+	let mut landmarks = vec![];
+	let mut expected = None;
+	let mut inside = false;
+	for (number, line) in contents.lines().enumerate() {
+		let line = line.trim();
+		if let Some(count) = line.strip_prefix("n_points:") {
+			expected = Some(count.trim().parse::<usize>().with_context(|| format!("Bad point count on line {}.", number + 1))?);
+		} else if line == "{" {
+			inside = true;
+		} else if line == "}" {
+			inside = false;
+		} else if inside && !line.is_empty() {
+			let values: Vec<f32> = line.split_whitespace().map(|v| v.parse::<f32>()).collect::<Result<_, _>>()
+				.with_context(|| format!("Couldn't read a point from line {}: '{}'", number + 1, line))?;
+			if values.len() != 2 {
+				bail!("Expected an x and y on line {}, but found '{}'.", number + 1, line);
+			}
+			landmarks.push(Landmark { label: None, x: values[0], y: values[1] });
+		}
+	}
+	if let Some(expected) = expected {
+		if expected != landmarks.len() {
+			bail!("The header says there are {} points, but the file has {}.", expected, landmarks.len());
+		}
+	}
+	Ok(landmarks)
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<Landmark>> {
+	let mut landmarks = vec![];
+	for line in contents.lines() {
+		let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+		let (label, x, y) = match fields.as_slice() {
+			[x, y] => (None, x, y),
+			[label, x, y] => (Some(label.trim_matches('"').to_string()), x, y),
+			_ => continue,
+		};
+		if let (Ok(x), Ok(y)) = (x.parse::<f32>(), y.parse::<f32>()) {
+			landmarks.push(Landmark { label, x, y });
+		}
+	}
+	Ok(landmarks)
+}
+
+/// Read landmarks from a file, detecting the format if it isn't given.
+pub fn read_landmarks(path: &Path, format: Option<LandmarkFormat>) -> Result<Vec<Landmark>> {
+	let contents = std::fs::read_to_string(path).with_context(|| format!("Couldn't read {}.", path.display()))?;
+	let format = format.or_else(|| LandmarkFormat::detect(path, &contents))
+		.ok_or_else(|| anyhow!("Couldn't tell what kind of landmark file {} is.", path.display()))?;
+	parse_landmarks(&contents, format).with_context(|| format!("Couldn't read landmarks from {}.", path.display()))
+}
+
+/// A left point and the right point it's matched to, each (x, y).
+pub type LandmarkPair = ((f32, f32), (f32, f32));
+
+/// Pair up left and right landmarks into (left, right) point pairs.
+pub fn match_landmarks(left: &[Landmark], right: &[Landmark], matching: Matching) -> Result<Vec<LandmarkPair>> {
+	match matching {
+		Matching::Index => {
+			if left.len() != right.len() {
+				bail!("The left file has {} points and the right has {}, so they can't be paired by position.", left.len(), right.len());
+			}
+			Ok(left.iter().zip(right).map(|(l, r)| ((l.x, l.y), (r.x, r.y))).collect())
+		},
+		Matching::Label => {
+			let mut by_label = HashMap::new();
+			for landmark in right {
+				let label = landmark.label.as_ref().ok_or_else(|| anyhow!("The right file has unlabeled points, so they can't be paired by label."))?;
+				if by_label.insert(label.as_str(), landmark).is_some() {
+					bail!("The label '{}' is on more than one point in the right file.", label);
+				}
+			}
+			let mut pairs = vec![];
+			let mut seen = vec![];
+			for landmark in left {
+				let label = landmark.label.as_ref().ok_or_else(|| anyhow!("The left file has unlabeled points, so they can't be paired by label."))?;
+				if seen.contains(&label) {
+					bail!("The label '{}' is on more than one point in the left file.", label);
+				}
+				seen.push(label);
+				match by_label.get(label.as_str()) {
+					Some(r) => pairs.push(((landmark.x, landmark.y), (r.x, r.y))),
+					None => log::warn!("The label '{}' is only in the left file, so it's left out.", label),
+				}
+			}
+			Ok(pairs)
+		},
+	}
+}
+
+/// Read a left and right landmark file and add each matched pair as a new channel, keyed at `frame`.
+/// Returns the indices of the new channels.  Nothing is added if anything fails.
+pub fn import_landmarks(
+	animation: &mut Animation,
+	left_path: &Path,
+	right_path: &Path,
+	format: Option<LandmarkFormat>,
+	matching: Matching,
+	frame: u32,
+) -> Result<Vec<usize>> {
+	let left = read_landmarks(left_path, format)?;
+	let right = read_landmarks(right_path, format)?;
+	let pairs = match_landmarks(&left, &right, matching)?;
+	Ok(pairs.into_iter().map(|((lx, ly), (rx, ry))| animation.set_point(lx, ly, rx, ry, frame, None)).collect())
+}
+
+/// The formats points can be exported in.  Written files label each point with its channel index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum ExportFormat {
+	/// "channel,x,y" lines, one file per side and frame.
+	Csv,
+	/// ibug/dlib, one file per side and frame.
+	Pts,
+	/// Both sides and every frame in one KeyframeFile.
+	Json,
+	/// One file per side, with an image and an annotation for every frame.
+	Coco,
+}
+
+/// Which frames of the animation to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum FrameSelection {
+	Frame(u32),
+	/// Every frame with a keypoint in any channel.  Channels without a keypoint there are interpolated.
+	Keyframes,
+}
+
+/// Keyframe-major points, the layout of the Python prototype's Animation, which `Animation.save_json` writes.
+/// Every keyframe holds every point, and the nth point of each keyframe is the same correspondence, so it becomes
+/// channel n.  Coordinates are image pixels.
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "keyframes": [
+///     {"frame": 0, "points_left": [[x, y], ...], "points_right": [[x, y], ...]},
+///     {"frame": 24, "points_left": [[x, y], ...], "points_right": [[x, y], ...]}
+///   ]
+/// }
+/// ```
+///
+/// `version` is KEYFRAME_FILE_VERSION.  Frames are non-negative integers, unique, in any order.  Within a keyframe
+/// `points_left` and `points_right` are the same length, and every keyframe has the same number of points.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct KeyframeFile {
+	pub version: u32,
+	pub keyframes: Vec<KeyframeEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct KeyframeEntry {
+	pub frame: u32,
+	pub points_left: Vec<[f32; 2]>,
+	pub points_right: Vec<[f32; 2]>,
+}
+
+pub const KEYFRAME_FILE_VERSION: u32 = 1;
+
+impl KeyframeFile {
+	/// Convert to the channel-major Animation.  Each point becomes a channel with a keypoint at every keyframe, so
+	/// frames between keyframes interpolate the same way the prototype does.
+	pub fn to_animation(&self) -> Result<Animation> {
+		if self.version != KEYFRAME_FILE_VERSION {
+			bail!("Keyframe file version {} isn't supported.  Expected {}.", self.version, KEYFRAME_FILE_VERSION);
+		}
+		let num_points = self.keyframes.first().map(|k| k.points_left.len()).unwrap_or(0);
+		let mut frames = vec![];
+		for keyframe in &self.keyframes {
+			if keyframe.points_left.len() != num_points || keyframe.points_right.len() != num_points {
+				bail!(
+					"Keyframe {} has {} left and {} right points, but every keyframe needs {}.",
+					keyframe.frame, keyframe.points_left.len(), keyframe.points_right.len(), num_points,
+				);
+			}
+			if frames.contains(&keyframe.frame) {
+				bail!("Frame {} has more than one keyframe.", keyframe.frame);
+			}
+			frames.push(keyframe.frame);
+		}
+		let mut animation = Animation::new();
+		for (keyframe_idx, keyframe) in self.keyframes.iter().enumerate() {
+			for (channel, (left, right)) in keyframe.points_left.iter().zip(&keyframe.points_right).enumerate() {
+				// The first keyframe makes the channels, in order, and the rest add keypoints to them.
+				let channel_idx = if keyframe_idx == 0 { None } else { Some(channel) };
+				animation.set_point(left[0], left[1], right[0], right[1], keyframe.frame, channel_idx);
+			}
+		}
+		Ok(animation)
+	}
+}
+
+/// Read a keyframe JSON file, like one saved by the Python prototype, into an Animation.
+pub fn read_keyframe_file(path: &Path) -> Result<Animation> {
+	let contents = std::fs::read_to_string(path).with_context(|| format!("Couldn't read {}.", path.display()))?;
+	let file: KeyframeFile = serde_json::from_str(&contents).with_context(|| format!("{} isn't a keyframe file.", path.display()))?;
+	file.to_animation()
+}
+
+/// Write the animation's points for the selected frames to `directory`, with file names starting with `name`.
+/// Per side formats go to `{name}_left` and `{name}_right`, plus `_{frame}` when writing every keyframe.
+/// Returns the paths written.
+pub fn export_points(animation: &Animation, directory: &Path, name: &str, format: ExportFormat, frames: FrameSelection) -> Result<Vec<PathBuf>> {
+	let numbered = frames == FrameSelection::Keyframes;
+	let frames = match frames {
+		FrameSelection::Frame(frame) => vec![frame],
+		FrameSelection::Keyframes => animation.get_keyframes(),
+	};
+	if frames.is_empty() {
+		bail!("The animation has no points to export.");
+	}
+	std::fs::create_dir_all(directory)?;
+	let point_sets: Vec<(u32, Vec<f32>, Vec<f32>)> = frames.iter().map(|&f| {
+		let (left, right) = animation.get_points(f);
+		(f, left, right)
+	}).collect();
+	let mut written = vec![];
+	let mut write = |file_name: String, contents: String| -> Result<()> {
+		let path = directory.join(file_name);
+		std::fs::write(&path, contents).with_context(|| format!("Couldn't write {}.", path.display()))?;
+		written.push(path);
+		Ok(())
+	};
+	match format {
+		ExportFormat::Json => {
+			let file = KeyframeFile {
+				version: KEYFRAME_FILE_VERSION,
+				keyframes: point_sets.iter().map(|(frame, left, right)| KeyframeEntry { frame: *frame, points_left: pairs(left), points_right: pairs(right) }).collect(),
+			};
+			write(format!("{}.json", name), serde_json::to_string_pretty(&file)?)?;
+		},
+		ExportFormat::Coco => {
+			let left: Vec<(u32, &Vec<f32>)> = point_sets.iter().map(|(f, l, _)| (*f, l)).collect();
+			let right: Vec<(u32, &Vec<f32>)> = point_sets.iter().map(|(f, _, r)| (*f, r)).collect();
+			for (side, sets) in [("left", left), ("right", right)] {
+				write(format!("{}_{}.json", name, side), format_coco(&sets, animation.get_num_channels())?)?;
+			}
+		},
+		ExportFormat::Csv | ExportFormat::Pts => {
+			let extension = if format == ExportFormat::Csv { "csv" } else { "pts" };
+			for (frame, left, right) in &point_sets {
+				for (side, points) in [("left", left), ("right", right)] {
+					let file_name = if numbered {
+						format!("{}_{}_{:04}.{}", name, side, frame, extension)
+					} else {
+						format!("{}_{}.{}", name, side, extension)
+					};
+					let contents = if format == ExportFormat::Csv { format_csv(points) } else { format_pts(points) };
+					write(file_name, contents)?;
+				}
+			}
+		},
+	}
+	Ok(written)
+}
+
+fn pairs(points: &[f32]) -> Vec<[f32; 2]> {
+	points.chunks_exact(2).map(|p| [p[0], p[1]]).collect()
+}
+
+fn format_csv(points: &[f32]) -> String {
+	let mut text = String::from("channel,x,y\n");
+	for (i, p) in points.chunks_exact(2).enumerate() {
+		writeln!(text, "{},{},{}", i, p[0], p[1]).unwrap();
+	}
+	text
+}
+
+fn format_pts(points: &[f32]) -> String {
+	let mut text = format!("version: 1\nn_points: {}\n{{\n", points.len() / 2);
+	for p in points.chunks_exact(2) {
+		writeln!(text, "{} {}", p[0], p[1]).unwrap();
+	}
+	text.push_str("}\n");
+	text
+}
+
+/// A COCO keypoint file with one image and annotation per frame, in order, so annotation n is the nth frame.
+/// The images only have ids and placeholder names, since the points don't say which files they belong to.
+fn format_coco(point_sets: &[(u32, &Vec<f32>)], num_channels: usize) -> Result<String> {
+	let images: Vec<serde_json::Value> = point_sets.iter().map(|(frame, _)| serde_json::json!({
+		"id": frame,
+		"file_name": format!("frame_{:04}", frame),
+	})).collect();
+	let annotations: Vec<serde_json::Value> = point_sets.iter().enumerate().map(|(i, (frame, points))| serde_json::json!({
+		"id": i + 1,
+		"image_id": frame,
+		"category_id": 1,
+		"num_keypoints": points.len() / 2,
+		"keypoints": points.chunks_exact(2).flat_map(|p| [p[0], p[1], 2.0]).collect::<Vec<f32>>(),
+	})).collect();
+	let file = serde_json::json!({
+		"images": images,
+		"annotations": annotations,
+		"categories": [{
+			"id": 1,
+			"name": "morph",
+			"keypoints": (0..num_channels).map(|c| c.to_string()).collect::<Vec<String>>(),
+		}],
+	});
+	Ok(serde_json::to_string_pretty(&file)?)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::export::tests::scratch_directory;
+
+	const PTS: &str = "version: 1\nn_points:  3\n{\n10.5 20\n30 40\n50 60.25\n}\n";
+	const LABELME: &str = r#"{"version": "5.0.1", "shapes": [
+		{"label": "nose", "points": [[5.0, 6.0]], "shape_type": "point"},
+		{"label": "outline", "points": [[0, 0], [1, 1]], "shape_type": "polygon"},
+		{"label": "chin", "points": [[7.0, 8.0]], "shape_type": "point"}
+	], "imagePath": "a.png"}"#;
+	const COCO: &str = r#"{
+		"categories": [{"id": 1, "name": "face", "keypoints": ["chin", "nose", "ear"]}],
+		"annotations": [
+			{"id": 1, "image_id": 1, "category_id": 1, "keypoints": [1, 2, 2, 3, 4, 1, 0, 0, 0]},
+			{"id": 2, "image_id": 2, "category_id": 1, "keypoints": [9, 9, 2, 8, 8, 2, 7, 7, 2]}
+		]
+	}"#;
+
+	#[test]
+	fn test_parse_formats() {
+		let pts = parse_landmarks(PTS, LandmarkFormat::Pts).unwrap();
+		assert_eq!(pts.len(), 3);
+		assert_eq!((pts[0].x, pts[2].y), (10.5, 60.25));
+		assert!(parse_landmarks("n_points: 4\n{\n1 2\n}\n", LandmarkFormat::Pts).is_err());
+
+		let csv = parse_landmarks("x,y\n1,2\n\n3.5, 4\n", LandmarkFormat::Csv).unwrap();
+		assert_eq!(csv, vec![Landmark { label: None, x: 1.0, y: 2.0 }, Landmark { label: None, x: 3.5, y: 4.0 }]);
+		let labeled = parse_landmarks("\"nose\",1,2\n", LandmarkFormat::Csv).unwrap();
+		assert_eq!(labeled[0].label.as_deref(), Some("nose"));
+
+		let labelme = parse_landmarks(LABELME, LandmarkFormat::LabelMe).unwrap();
+		assert_eq!(labelme.iter().map(|l| l.label.clone().unwrap()).collect::<Vec<_>>(), vec!["nose", "chin"]);
+
+		// The unplaced ear is left out.
+		let coco = parse_landmarks(COCO, LandmarkFormat::Coco { annotation: 0 }).unwrap();
+		assert_eq!(coco, vec![
+			Landmark { label: Some("chin".to_string()), x: 1.0, y: 2.0 },
+			Landmark { label: Some("nose".to_string()), x: 3.0, y: 4.0 },
+		]);
+		assert_eq!(parse_landmarks(COCO, LandmarkFormat::Coco { annotation: 1 }).unwrap().len(), 3);
+		assert!(parse_landmarks(COCO, LandmarkFormat::Coco { annotation: 2 }).is_err());
+
+		assert_eq!(LandmarkFormat::detect(Path::new("a.json"), LABELME), Some(LandmarkFormat::LabelMe));
+		assert_eq!(LandmarkFormat::detect(Path::new("a.json"), COCO), Some(LandmarkFormat::Coco { annotation: 0 }));
+		assert_eq!(LandmarkFormat::detect(Path::new("face.PTS"), ""), Some(LandmarkFormat::Pts));
+	}
+
+	#[test]
+	fn test_matching_and_import() {
+		let left = parse_landmarks(LABELME, LandmarkFormat::LabelMe).unwrap();
+		let right = parse_landmarks(COCO, LandmarkFormat::Coco { annotation: 1 }).unwrap();
+		// Right is chin, nose, ear.  Pairing by label follows the left order.
+		assert_eq!(match_landmarks(&left, &right, Matching::Label).unwrap(), vec![((5.0, 6.0), (8.0, 8.0)), ((7.0, 8.0), (9.0, 9.0))]);
+		assert!(match_landmarks(&left, &right, Matching::Index).is_err());
+		let unlabeled = parse_landmarks(PTS, LandmarkFormat::Pts).unwrap();
+		assert!(match_landmarks(&unlabeled, &unlabeled, Matching::Label).is_err());
+
+		let directory = scratch_directory("landmarks");
+		std::fs::create_dir_all(&directory).unwrap();
+		std::fs::write(directory.join("left.pts"), PTS).unwrap();
+		std::fs::write(directory.join("right.csv"), "1,1\n2,2\n3,3\n").unwrap();
+		let mut animation = Animation::new();
+		animation.set_point(0.0, 0.0, 0.0, 0.0, 0, None);
+		let channels = import_landmarks(&mut animation, &directory.join("left.pts"), &directory.join("right.csv"), None, Matching::Index, 5).unwrap();
+		assert_eq!(channels, vec![1, 2, 3]);
+		let (left_points, right_points) = animation.get_points(5);
+		assert_eq!(left_points, vec![0.0, 0.0, 10.5, 20.0, 30.0, 40.0, 50.0, 60.25]);
+		assert_eq!(right_points, vec![0.0, 0.0, 1.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+		std::fs::remove_dir_all(&directory).unwrap();
+	}
+
+	#[test]
+	fn test_export_round_trip() {
+		let mut animation = Animation::new();
+		let a = animation.set_point(1.0, 2.0, 3.0, 4.0, 0, None);
+		animation.set_point(5.5, 6.0, 7.0, 8.0, 0, None);
+		animation.set_point(11.0, 12.0, 13.0, 14.0, 10, Some(a));
+		let directory = scratch_directory("landmark_export");
+
+		// One frame of each per side format reads back as the same points, labeled by channel where it can be.
+		for format in [ExportFormat::Csv, ExportFormat::Pts] {
+			let written = export_points(&animation, &directory, "points", format, FrameSelection::Frame(5)).unwrap();
+			assert_eq!(written.len(), 2);
+			let left = read_landmarks(&written[0], None).unwrap();
+			let right = read_landmarks(&written[1], None).unwrap();
+			let matching = if format == ExportFormat::Csv { Matching::Label } else { Matching::Index };
+			assert_eq!(match_landmarks(&left, &right, matching).unwrap(), vec![((6.0, 7.0), (8.0, 9.0)), ((5.5, 6.0), (7.0, 8.0))]);
+		}
+		let written = export_points(&animation, &directory, "points", ExportFormat::Pts, FrameSelection::Keyframes).unwrap();
+		assert_eq!(written, ["left_0000", "right_0000", "left_0010", "right_0010"].map(|s| directory.join(format!("points_{}.pts", s))));
+
+		// COCO has an annotation per keyframe.
+		let written = export_points(&animation, &directory, "points", ExportFormat::Coco, FrameSelection::Keyframes).unwrap();
+		let last = read_landmarks(&written[1], Some(LandmarkFormat::Coco { annotation: 1 })).unwrap();
+		assert_eq!(last[0], Landmark { label: Some("0".to_string()), x: 13.0, y: 14.0 });
+
+		let written = export_points(&animation, &directory, "points", ExportFormat::Json, FrameSelection::Keyframes).unwrap();
+		let file: KeyframeFile = serde_json::from_str(&std::fs::read_to_string(&written[0]).unwrap()).unwrap();
+		assert_eq!(file.keyframes.len(), 2);
+		assert_eq!(file.keyframes[1].points_left, vec![[11.0, 12.0], [5.5, 6.0]]);
+		assert!(export_points(&Animation::new(), &directory, "points", ExportFormat::Json, FrameSelection::Keyframes).is_err());
+		std::fs::remove_dir_all(&directory).unwrap();
+	}
+
+	#[test]
+	fn test_keyframes_to_animation() {
+		// Two points over keyframes given out of order, as the prototype would save them.
+		let text = r#"{"version": 1, "keyframes": [
+			{"frame": 10, "points_left": [[10, 0], [20, 20]], "points_right": [[0, 10], [30, 30]]},
+			{"frame": 0, "points_left": [[0, 0], [20, 20]], "points_right": [[0, 0], [30, 30]]}
+		]}"#;
+		let file: KeyframeFile = serde_json::from_str(text).unwrap();
+		let animation = file.to_animation().unwrap();
+		assert_eq!(animation.get_num_channels(), 2);
+		assert_eq!(animation.get_keyframes(), vec![0, 10]);
+		assert_eq!(animation.get_points(5), (vec![5.0, 0.0, 20.0, 20.0], vec![0.0, 5.0, 30.0, 30.0]));
+		assert_eq!(animation.get_points(20), (vec![10.0, 0.0, 20.0, 20.0], vec![0.0, 10.0, 30.0, 30.0]));
+
+		// Exporting the keyframes gives the same file back, sorted by frame.
+		let directory = scratch_directory("keyframes");
+		let written = export_points(&animation, &directory, "project", ExportFormat::Json, FrameSelection::Keyframes).unwrap();
+		let round_trip = read_keyframe_file(&written[0]).unwrap();
+		assert_eq!(round_trip.get_points(5), animation.get_points(5));
+		std::fs::remove_dir_all(&directory).unwrap();
+
+		let mut ragged = file.clone();
+		ragged.keyframes[1].points_right.pop();
+		assert!(ragged.to_animation().is_err());
+		let mut repeated = file.clone();
+		repeated.keyframes[1].frame = 10;
+		assert!(repeated.to_animation().is_err());
+		assert!(KeyframeFile { version: 2, ..file }.to_animation().is_err());
+	}
+}
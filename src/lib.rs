@@ -1,6 +1,21 @@
+pub mod animated_export;
 pub mod animation_system;
+pub mod annotation;
+pub mod bitmap_font;
+pub mod color;
+pub mod color_match;
+pub mod compact_spline;
+pub mod contact_sheet;
+pub mod debug_overlay;
+pub mod export;
 pub mod image_source;
+pub mod landmarks;
+pub mod linalg;
+pub mod morph;
+pub mod moving_least_squares;
 pub mod thin_plate_spline;
+pub mod video_export;
+pub mod warp;
 
 
 
@@ -0,0 +1,102 @@
+// The handful of dense linear algebra routines the splines need, over whichever backend was picked with cargo features.
+// `linalg_faer` (the default) is pure Rust and builds anywhere.  `linalg_mkl` and `linalg_openblas` go through
+// ndarray-linalg and LAPACK.  If more than one is enabled, the LAPACK backends win.
+
+use anyhow::Result;
+use ndarray::prelude::*;
+
+#[cfg(not(any(feature = "linalg_faer", feature = "linalg_mkl", feature = "linalg_openblas")))]
+compile_error!("No linear algebra backend selected.  Enable one of the features linalg_faer, linalg_mkl, or linalg_openblas.");
+
+/// Solve the square system ax = b with a pivoted LU decomposition.
+/// Singular matrices may produce an error or non-finite values depending on the backend, so check both.
+pub fn lu_solve(a: &Array2<f64>, b: &Array2<f64>) -> Result<Array2<f64>> {
+	assert_eq!(a.nrows(), a.ncols());
+	assert_eq!(a.nrows(), b.nrows());
+	backend::lu_solve(a, b)
+}
+
+/// Full singular value decomposition, a = u diag(s) vt.
+/// u is (m, m), s has min(m, n) entries sorted in descending order, and vt is (n, n).
+pub fn svd(a: &Array2<f32>) -> Result<(Array2<f32>, Array1<f32>, Array2<f32>)> {
+	backend::svd(a)
+}
+
+#[cfg(any(feature = "linalg_mkl", feature = "linalg_openblas"))]
+mod backend {
+	use anyhow::Result;
+	use ndarray::prelude::*;
+	use ndarray_linalg::{Factorize, Solve, SVD};
+
+	pub fn lu_solve(a: &Array2<f64>, b: &Array2<f64>) -> Result<Array2<f64>> {
+		let factors = a.factorize()?;
+		let mut x = Array2::<f64>::zeros((a.ncols(), b.ncols()));
+		for (column_idx, column) in b.columns().into_iter().enumerate() {
+			let solution = factors.solve(&column)?;
+			x.column_mut(column_idx).assign(&solution);
+		}
+		Ok(x)
+	}
+
+	pub fn svd(a: &Array2<f32>) -> Result<(Array2<f32>, Array1<f32>, Array2<f32>)> {
+		let (u, s, vt) = a.svd(true, true)?;
+		let u = u.expect("calc_u is true but u is not present!?");
+		let vt = vt.expect("calc_v is true but v is not present!?");
+		Ok((u, s, vt))
+	}
+}
+
+#[cfg(all(feature = "linalg_faer", not(any(feature = "linalg_mkl", feature = "linalg_openblas"))))]
+mod backend {
+	use anyhow::{anyhow, Result};
+	use faer::prelude::*;
+	use ndarray::prelude::*;
+
+	pub fn lu_solve(a: &Array2<f64>, b: &Array2<f64>) -> Result<Array2<f64>> {
+		let a_mat = faer::Mat::<f64>::from_fn(a.nrows(), a.ncols(), |i, j| a[(i, j)]);
+		let b_mat = faer::Mat::<f64>::from_fn(b.nrows(), b.ncols(), |i, j| b[(i, j)]);
+		let x = a_mat.partial_piv_lu().solve(&b_mat);
+		Ok(Array2::from_shape_fn((x.nrows(), x.ncols()), |(i, j)| x[(i, j)]))
+	}
+
+	pub fn svd(a: &Array2<f32>) -> Result<(Array2<f32>, Array1<f32>, Array2<f32>)> {
+		let a_mat = faer::Mat::<f32>::from_fn(a.nrows(), a.ncols(), |i, j| a[(i, j)]);
+		let decomposition = a_mat.svd().map_err(|e| anyhow!("SVD failed to converge: {e:?}"))?;
+		let u = decomposition.U();
+		let s = decomposition.S().column_vector();
+		let v = decomposition.V();
+		Ok((
+			Array2::from_shape_fn((u.nrows(), u.ncols()), |(i, j)| u[(i, j)]),
+			Array1::from_shape_fn(s.nrows(), |i| s[i]),
+			// faer gives us V, not V^T.
+			Array2::from_shape_fn((v.ncols(), v.nrows()), |(i, j)| v[(j, i)]),
+		))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use approx::assert_abs_diff_eq;
+
+	#[test]
+	fn test_lu_solve() {
+		let a = array![[2.0f64, 1.0, 0.0], [1.0, 3.0, 1.0], [0.0, 1.0, 4.0]];
+		let x = array![[1.0f64, -1.0], [2.0, 0.5], [-3.0, 2.0]];
+		let b = a.dot(&x);
+		assert_abs_diff_eq!(lu_solve(&a, &b).unwrap(), x, epsilon = 1e-10);
+	}
+
+	#[test]
+	fn test_svd_reconstructs() {
+		let a = array![[1.0f32, 2.0], [3.0, 4.0], [5.0, 6.0]];
+		let (u, s, vt) = svd(&a).unwrap();
+		assert_eq!(u.dim(), (3, 3));
+		assert_eq!(s.len(), 2);
+		assert_eq!(vt.dim(), (2, 2));
+		assert!(s[0] >= s[1]);
+		let reconstructed = u.slice(s![.., ..2]).dot(&Array2::from_diag(&s)).dot(&vt);
+		assert_abs_diff_eq!(reconstructed, a, epsilon = 1e-4);
+	}
+}
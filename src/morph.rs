@@ -0,0 +1,488 @@
+// Rendering a single morphed frame, the Rust side of the prototype's morph().
+// We find the mapping from the morph points to the left image AND from the morph points to the right image.
+// Then for each output pixel we pull the matching left and right pixels and blend them.
+
+use image::{DynamicImage, GrayImage, Rgba, Rgba32FImage, RgbaImage};
+
+use crate::color::{luminance, BlendSpace};
+use crate::color_match::{ColorMatch, ColorTransfer};
+use crate::warp::WarpMethod;
+
+/// What a source image looks like outside its own bounds, where the warp can easily pull from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum OutOfBounds {
+	/// Fully transparent, so cut-out subjects stay cut out.
+	Transparent,
+	/// Repeat the nearest edge pixel, like the prototype.
+	#[default]
+	Clamp,
+	/// Reflect the image back on itself at the edges.
+	Mirror,
+	/// Tile the image.
+	Wrap,
+	/// A fixed sRGB color with alpha.
+	Solid { color: [u8; 4] },
+}
+
+/// Everything about how a frame is rendered, apart from the points and images themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Deserialize, serde::Serialize)]
+pub struct MorphSettings {
+	pub warp: WarpMethod,
+	pub blend_space: BlendSpace,
+	pub out_of_bounds: OutOfBounds,
+	/// Match the right image's colors to the left.  The match is full at blend 0 and fades out by blend 1.
+	pub color_match: ColorMatch,
+}
+
+/// Linearly interpolate between two sets of corresponding points in the form [x, y, x, y, ...].
+/// This gives the morph points for a given blend amount, 0 at the left and 1 at the right.
+pub fn interpolate_points(left_points: &Vec<f32>, right_points: &Vec<f32>, amount: f32) -> Vec<f32> {
+	assert_eq!(left_points.len(), right_points.len());
+	left_points.iter().zip(right_points.iter()).map(|(l, r)| l + amount*(r - l)).collect()
+}
+
+fn lerp4(a: [f32; 4], b: [f32; 4], amount: f32) -> [f32; 4] {
+	[
+		a[0] + amount*(b[0] - a[0]),
+		a[1] + amount*(b[1] - a[1]),
+		a[2] + amount*(b[2] - a[2]),
+		a[3] + amount*(b[3] - a[3]),
+	]
+}
+
+fn with_alpha(rgb: [f32; 3], alpha: f32) -> [f32; 4] {
+	[rgb[0], rgb[1], rgb[2], alpha]
+}
+
+/// Convert an encoded sRGBA pixel, 0-1, into the blend space with the color premultiplied by alpha.
+/// Premultiplied colors can be filtered and blended like any other numbers.  Straight alpha would drag in the
+/// meaningless color under transparent pixels, which shows up as dark or colored fringes around cut-outs.
+fn premultiply(rgba: [f32; 4], space: BlendSpace) -> [f32; 4] {
+	let [c0, c1, c2] = space.decode([rgba[0], rgba[1], rgba[2]]);
+	let a = rgba[3];
+	[c0*a, c1*a, c2*a, a]
+}
+
+/// The inverse of `premultiply`, back to encoded sRGBA, 0-1.
+fn unpremultiply(color: [f32; 4], space: BlendSpace) -> [f32; 4] {
+	let a = color[3].clamp(0.0, 1.0);
+	if a <= 1e-6 {
+		return [0.0; 4];
+	}
+	let [c0, c1, c2] = space.encode([color[0] / a, color[1] / a, color[2] / a]);
+	[c0, c1, c2, a]
+}
+
+/// A source image converted into the blend space and premultiplied once up front, so sampling and blending are
+/// plain arithmetic.
+struct DecodedImage {
+	width: u32,
+	height: u32,
+	pixels: Vec<[f32; 4]>,
+	out_of_bounds: OutOfBounds,
+	// The solid out of bounds color, already decoded.
+	border: [f32; 4],
+}
+
+impl DecodedImage {
+	/// If given a color transfer, it's applied at the given strength, 0 for none and 1 for all.
+	fn new(image: &DynamicImage, space: BlendSpace, out_of_bounds: OutOfBounds, transfer: Option<(&ColorTransfer, f32)>) -> Self {
+		let rgba = image.to_rgba32f();
+		let border = match out_of_bounds {
+			OutOfBounds::Solid { color } => premultiply(color.map(|c| c as f32 / 255.0), space),
+			_ => [0.0; 4],
+		};
+		let decode = |p: [f32; 4]| {
+			let color = premultiply(p, space);
+			match transfer {
+				Some((transfer, strength)) if strength > 0.0 => {
+					let matched = premultiply(with_alpha(transfer.apply([p[0], p[1], p[2]]), p[3]), space);
+					lerp4(color, matched, strength)
+				},
+				_ => color,
+			}
+		};
+		Self {
+			width: rgba.width(),
+			height: rgba.height(),
+			pixels: rgba.pixels().map(|p| decode(p.0)).collect(),
+			out_of_bounds,
+			border,
+		}
+	}
+
+	/// The pixel at (x, y), or whatever the out of bounds mode says is there.
+	fn get(&self, x: i64, y: i64) -> [f32; 4] {
+		let (w, h) = (self.width as i64, self.height as i64);
+		let inside = x >= 0 && y >= 0 && x < w && y < h;
+		let (x, y) = if inside {
+			(x, y)
+		} else {
+			match self.out_of_bounds {
+				OutOfBounds::Transparent => return [0.0; 4],
+				OutOfBounds::Solid { .. } => return self.border,
+				OutOfBounds::Clamp => (x.clamp(0, w - 1), y.clamp(0, h - 1)),
+				OutOfBounds::Mirror => (mirror(x, w), mirror(y, h)),
+				OutOfBounds::Wrap => (x.rem_euclid(w), y.rem_euclid(h)),
+			}
+		};
+		self.pixels[y as usize * self.width as usize + x as usize]
+	}
+
+	/// Bilinear sample.  Integer coordinates land exactly on a pixel.
+	fn sample(&self, x: f32, y: f32) -> [f32; 4] {
+		let (x0, y0) = (x.floor(), y.floor());
+		let (fx, fy) = (x - x0, y - y0);
+		let (x0, y0) = (x0 as i64, y0 as i64);
+		let top = lerp4(self.get(x0, y0), self.get(x0 + 1, y0), fx);
+		let bottom = lerp4(self.get(x0, y0 + 1), self.get(x0 + 1, y0 + 1), fx);
+		lerp4(top, bottom, fy)
+	}
+}
+
+/// Reflect an index into 0..size, repeating the edge pixel: -1 -> 0, -2 -> 1, size -> size - 1.
+fn mirror(i: i64, size: i64) -> i64 {
+	let m = i.rem_euclid(2 * size);
+	if m < size { m } else { 2*size - 1 - m }
+}
+
+/// How the transition from left to right spreads over the frame.
+/// Apart from Uniform, every dissolve gives each output pixel a threshold from 0 to 1, the point in the transition
+/// where that pixel switches over.  Softness is how much of the transition each switch is spread across, from a hard
+/// cut near 0 to 1, where every pixel takes the whole transition but still starts and ends at its own time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Dissolve {
+	/// The same blend everywhere, like the prototype.
+	#[default]
+	Uniform,
+	/// A grayscale mask over the output frame.  Black switches first and white last.
+	/// It's stretched to the frame if the sizes don't match.
+	Mask { mask: GrayImage, softness: f32 },
+	/// Sweep across the frame.  An angle of 0 degrees wipes left to right, 90 top to bottom.
+	Wipe { angle: f32, softness: f32 },
+	/// Key on the brightness of the (warped) left image.  Bright areas switch first, or dark first if inverted.
+	Luminance { softness: f32, invert: bool },
+	/// Per control point timing.  Each point has a delay from 0 to 1, which spreads out around the point's position
+	/// in the output frame with a Gaussian falloff of the given radius in pixels.  Far from all the points,
+	/// pixels follow the average delay.
+	Local { delays: Vec<f32>, radius: f32, softness: f32 },
+}
+
+/// The blend at a pixel, given the overall transition amount and the pixel's threshold.
+fn threshold_blend(amount: f32, threshold: f32, softness: f32) -> f32 {
+	// Scaling by (1 + softness) makes sure that every threshold from 0 to 1 is fully off at 0 and fully on at 1.
+	let softness = softness.max(1e-3);
+	((amount * (1.0 + softness) - threshold) / softness).clamp(0.0, 1.0)
+}
+
+/// The two sides of a morph and the frame they're rendered into.
+pub struct MorphInput<'a> {
+	pub left_points: &'a Vec<f32>,
+	pub right_points: &'a Vec<f32>,
+	pub left_image: &'a DynamicImage,
+	pub right_image: &'a DynamicImage,
+	/// Where the features sit in the output frame, usually `interpolate_points` of the left and right points at the
+	/// same amount as the pixel blend.
+	pub morph_points: &'a Vec<f32>,
+	pub output_width: u32,
+	pub output_height: u32,
+	/// How the pixel blend varies over the frame.
+	pub dissolve: &'a Dissolve,
+}
+
+impl<'a> MorphInput<'a> {
+	/// The output frame is the size of the left image, with a uniform dissolve.
+	pub fn new(left_points: &'a Vec<f32>, right_points: &'a Vec<f32>, left_image: &'a DynamicImage, right_image: &'a DynamicImage, morph_points: &'a Vec<f32>) -> Self {
+		MorphInput {
+			left_points,
+			right_points,
+			left_image,
+			right_image,
+			morph_points,
+			output_width: left_image.width(),
+			output_height: left_image.height(),
+			dissolve: &Dissolve::Uniform,
+		}
+	}
+}
+
+/// Render one frame of the morph.  Pixel blend 0 is all left image, 1 is all right, or where it switches over with
+/// anything but a uniform dissolve.
+/// Sources of any type are accepted, and alpha is carried through premultiplied.  Opaque sources give an opaque result.
+pub fn morph(input: &MorphInput, pixel_blend: f32, settings: &MorphSettings) -> RgbaImage {
+	let frame = render_frame(input, pixel_blend, settings);
+	let mut result = RgbaImage::new(input.output_width, input.output_height);
+	for (out, p) in result.pixels_mut().zip(frame.pixels()) {
+		*out = Rgba(p.0.map(|c| (c * 255.0).round() as u8));
+	}
+	result
+}
+
+/// As `morph`, but without rounding the result to 8 bits.
+/// The result is encoded sRGB with straight (not premultiplied) alpha, all 0-1.  Use this for high bit depth output.
+pub fn render_frame(input: &MorphInput, pixel_blend: f32, settings: &MorphSettings) -> Rgba32FImage {
+	let (output_width, output_height, morph_points, dissolve) = (input.output_width, input.output_height, input.morph_points, input.dissolve);
+	let morph_to_left = settings.warp.fit(morph_points, input.left_points);
+	let morph_to_right = settings.warp.fit(morph_points, input.right_points);
+	let transfer = ColorTransfer::fit(settings.color_match, input.right_image, input.left_image);
+	let left = DecodedImage::new(input.left_image, settings.blend_space, settings.out_of_bounds, None);
+	let right = DecodedImage::new(input.right_image, settings.blend_space, settings.out_of_bounds, transfer.as_ref().map(|t| (t, 1.0 - pixel_blend)));
+
+	// The wipe threshold is the position along its direction, scaled so the frame covers 0 to 1.
+	let (wipe_x, wipe_y, wipe_min, wipe_range) = match dissolve {
+		Dissolve::Wipe { angle, .. } => {
+			let (dy, dx) = angle.to_radians().sin_cos();
+			let (w, h) = (output_width.saturating_sub(1) as f32, output_height.saturating_sub(1) as f32);
+			let projections = [0.0, dx*w, dy*h, dx*w + dy*h];
+			let min = projections.iter().cloned().fold(f32::INFINITY, f32::min);
+			let max = projections.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+			(dx, dy, min, (max - min).max(1e-6))
+		},
+		_ => (0.0, 0.0, 0.0, 1.0),
+	};
+	if let Dissolve::Local { delays, .. } = dissolve {
+		assert_eq!(delays.len() * 2, morph_points.len(), "Local dissolves need one delay per control point.");
+	}
+	let mean_delay = match dissolve {
+		Dissolve::Local { delays, .. } if !delays.is_empty() => delays.iter().sum::<f32>() / delays.len() as f32,
+		_ => 0.0,
+	};
+
+	let mut result = Rgba32FImage::new(output_width, output_height);
+	// A row at a time keeps the intermediate point buffers small for big frames.
+	let mut row = Vec::with_capacity(2 * output_width as usize);
+	for y in 0..output_height {
+		row.clear();
+		for x in 0..output_width {
+			row.push(x as f32);
+			row.push(y as f32);
+		}
+		let left_sources = morph_to_left.transform(&row);
+		let right_sources = morph_to_right.transform(&row);
+		for (x, (l, r)) in left_sources.chunks_exact(2).zip(right_sources.chunks_exact(2)).enumerate() {
+			let left_color = left.sample(l[0], l[1]);
+			let blend = match dissolve {
+				Dissolve::Uniform => pixel_blend,
+				Dissolve::Mask { mask, softness } => {
+					let mx = (x as u64 * mask.width() as u64 / output_width as u64) as u32;
+					let my = (y as u64 * mask.height() as u64 / output_height as u64) as u32;
+					threshold_blend(pixel_blend, mask.get_pixel(mx, my)[0] as f32 / 255.0, *softness)
+				},
+				Dissolve::Wipe { softness, .. } => {
+					let threshold = ((x as f32)*wipe_x + (y as f32)*wipe_y - wipe_min) / wipe_range;
+					threshold_blend(pixel_blend, threshold, *softness)
+				},
+				Dissolve::Luminance { softness, invert } => {
+					let [r, g, b, _] = unpremultiply(left_color, settings.blend_space);
+					let brightness = luminance([r, g, b]);
+					threshold_blend(pixel_blend, if *invert { brightness } else { 1.0 - brightness }, *softness)
+				},
+				Dissolve::Local { delays, radius, softness } => {
+					// A tiny background weight on the mean keeps pixels far from every point from dividing by zero.
+					let (mut weighted, mut total) = (1e-6 * mean_delay, 1e-6f32);
+					for (p, delay) in morph_points.chunks_exact(2).zip(delays.iter()) {
+						let (dx, dy) = (p[0] - x as f32, p[1] - y as f32);
+						let w = (-(dx*dx + dy*dy) / (radius*radius)).exp();
+						weighted += w * delay;
+						total += w;
+					}
+					threshold_blend(pixel_blend, weighted / total, *softness)
+				},
+			};
+			let color = lerp4(left_color, right.sample(r[0], r[1]), blend);
+			let rgba = unpremultiply(color, settings.blend_space);
+			result.put_pixel(x as u32, y, Rgba(rgba));
+		}
+	}
+	result
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::thin_plate_spline::RadialBasis;
+	use image::{Rgb, RgbImage};
+
+	fn corners(width: f32, height: f32) -> Vec<f32> {
+		vec![0.0, 0.0, width, 0.0, 0.0, height, width, height]
+	}
+
+	fn settings(blend_space: BlendSpace) -> MorphSettings {
+		MorphSettings {
+			warp: WarpMethod::ThinPlateSpline { alpha: Some(0.0), kernel: RadialBasis::ThinPlate },
+			blend_space,
+			out_of_bounds: OutOfBounds::Clamp,
+			color_match: ColorMatch::None,
+		}
+	}
+
+	#[test]
+	fn test_blend_spaces() {
+		let black = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([0, 0, 0])));
+		let white = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([255, 255, 255])));
+		let points = corners(7.0, 7.0);
+		let srgb = morph(&MorphInput::new(&points, &points, &black, &white, &points), 0.5, &settings(BlendSpace::Srgb));
+		assert_eq!(srgb.get_pixel(3, 3).0, [128, 128, 128, 255]);
+		let linear = morph(&MorphInput::new(&points, &points, &black, &white, &points), 0.5, &settings(BlendSpace::LinearLight));
+		assert_eq!(linear.get_pixel(3, 3).0, [188, 188, 188, 255]);
+		let oklab = morph(&MorphInput::new(&points, &points, &black, &white, &points), 0.5, &settings(BlendSpace::OkLab));
+		assert_eq!(oklab.get_pixel(3, 3).0, [99, 99, 99, 255]);
+	}
+
+	#[test]
+	fn test_endpoints_reproduce_sources() {
+		let mut left = RgbImage::from_pixel(16, 16, Rgb([10, 20, 30]));
+		left.put_pixel(4, 5, Rgb([200, 100, 50]));
+		let mut right = RgbImage::from_pixel(16, 16, Rgb([90, 90, 90]));
+		right.put_pixel(10, 5, Rgb([0, 254, 0]));
+		let (left, right) = (DynamicImage::ImageRgb8(left), DynamicImage::ImageRgb8(right));
+		// The right image is the left shifted 6 pixels over.
+		let left_points = corners(15.0, 15.0);
+		let right_points: Vec<f32> = left_points.chunks_exact(2).flat_map(|p| [p[0] + 6.0, p[1]]).collect();
+		for space in [BlendSpace::Srgb, BlendSpace::LinearLight, BlendSpace::OkLab] {
+			let at_left = morph(&MorphInput::new(&left_points, &right_points, &left, &right, &left_points), 0.0, &settings(space));
+			assert_eq!(at_left.get_pixel(4, 5).0, [200, 100, 50, 255], "{space:?}");
+			let at_right = morph(&MorphInput::new(&left_points, &right_points, &left, &right, &right_points), 1.0, &settings(space));
+			assert_eq!(at_right.get_pixel(10, 5).0, [0, 254, 0, 255], "{space:?}");
+		}
+		// Half way, both features meet in the middle.
+		let middle_points = interpolate_points(&left_points, &right_points, 0.5);
+		assert_eq!(middle_points[0], 3.0);
+		let middle = morph(&MorphInput::new(&left_points, &right_points, &left, &right, &middle_points), 0.5, &settings(BlendSpace::Srgb));
+		assert_eq!(middle.get_pixel(7, 5).0, [100, 177, 25, 255]);
+	}
+
+	#[test]
+	fn test_premultiplied_alpha() {
+		// A red square cut out of a transparent background which is secretly bright green underneath.
+		let mut left = RgbaImage::from_pixel(16, 16, Rgba([0, 255, 0, 0]));
+		for y in 4..12 {
+			for x in 4..12 {
+				left.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+			}
+		}
+		let right = RgbaImage::from_pixel(16, 16, Rgba([0, 0, 255, 0]));
+		let (left, right) = (DynamicImage::ImageRgba8(left), DynamicImage::ImageRgba8(right));
+		let points = corners(15.0, 15.0);
+		// Shift by half a pixel so the edge of the square gets filtered.
+		let shifted: Vec<f32> = points.chunks_exact(2).flat_map(|p| [p[0] + 0.5, p[1]]).collect();
+		let out = morph(&MorphInput::new(&points, &points, &left, &right, &shifted), 0.5, &settings(BlendSpace::LinearLight));
+		// Inside, half as opaque but still pure red.
+		assert_eq!(out.get_pixel(8, 8).0, [255, 0, 0, 128]);
+		// On the filtered edge, no green or blue bleeds in from the transparent pixels.
+		let edge = out.get_pixel(4, 8).0;
+		assert_eq!(&edge[..3], &[255, 0, 0]);
+		assert!(edge[3] > 0 && edge[3] < 128);
+		assert_eq!(out.get_pixel(1, 1).0[3], 0);
+	}
+
+	#[test]
+	fn test_out_of_bounds() {
+		// A 4 pixel gradient, sampled 2 pixels to the left of its start and 2 to the right of its end.
+		let mut image = RgbaImage::new(4, 1);
+		for x in 0..4 {
+			image.put_pixel(x, 0, Rgba([x as u8 * 50 + 10, 0, 0, 255]));
+		}
+		let image = DynamicImage::ImageRgba8(image);
+		let points = vec![0.0f32, 0.0, 3.0, 0.0, 0.0, 3.0, 3.0, 3.0];
+		let output: Vec<f32> = points.chunks_exact(2).flat_map(|p| [p[0] + 2.0, p[1]]).collect();
+		let cases = [
+			(OutOfBounds::Transparent, [0, 0, 0, 0], [0, 0, 0, 0]),
+			(OutOfBounds::Clamp, [10, 0, 0, 255], [160, 0, 0, 255]),
+			(OutOfBounds::Mirror, [60, 0, 0, 255], [110, 0, 0, 255]),
+			(OutOfBounds::Wrap, [110, 0, 0, 255], [60, 0, 0, 255]),
+			(OutOfBounds::Solid { color: [1, 2, 3, 255] }, [1, 2, 3, 255], [1, 2, 3, 255]),
+		];
+		for (out_of_bounds, before, after) in cases {
+			let settings = MorphSettings { out_of_bounds, ..settings(BlendSpace::Srgb) };
+			// Output x samples the image at x - 2.
+			let input = MorphInput { output_width: 8, output_height: 1, ..MorphInput::new(&points, &points, &image, &image, &output) };
+			let out = morph(&input, 0.0, &settings);
+			assert_eq!(out.get_pixel(0, 0).0, before, "{out_of_bounds:?}");
+			assert_eq!(out.get_pixel(2, 0).0, [10, 0, 0, 255], "{out_of_bounds:?}");
+			assert_eq!(out.get_pixel(7, 0).0, after, "{out_of_bounds:?}");
+		}
+	}
+
+	#[test]
+	fn test_dissolves() {
+		let black = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 16, Rgb([0, 0, 0])));
+		let white = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 16, Rgb([255, 255, 255])));
+		let points = corners(15.0, 15.0);
+		let settings = settings(BlendSpace::Srgb);
+		let render = |amount: f32, dissolve: &Dissolve| morph(&MorphInput { dissolve, ..MorphInput::new(&points, &points, &black, &white, &points) }, amount, &settings);
+
+		assert_eq!(render(0.25, &Dissolve::Uniform), morph(&MorphInput::new(&points, &points, &black, &white, &points), 0.25, &settings));
+
+		// Left to right, and every pixel starts at the left image and ends at the right.
+		let wipe = Dissolve::Wipe { angle: 0.0, softness: 0.01 };
+		let half = render(0.5, &wipe);
+		assert_eq!(half.get_pixel(2, 9)[0], 255);
+		assert_eq!(half.get_pixel(13, 9)[0], 0);
+		assert!(render(0.0, &wipe).pixels().all(|p| p[0] == 0));
+		assert!(render(1.0, &wipe).pixels().all(|p| p[0] == 255));
+		// Top to bottom.
+		let down = render(0.5, &Dissolve::Wipe { angle: 90.0, softness: 0.01 });
+		assert_eq!(down.get_pixel(9, 2)[0], 255);
+		assert_eq!(down.get_pixel(9, 13)[0], 0);
+
+		// A half size mask, dark on the right, is stretched over the frame.
+		let mut mask = GrayImage::from_pixel(8, 8, image::Luma([255]));
+		for y in 0..8 {
+			for x in 4..8 {
+				mask.put_pixel(x, y, image::Luma([0]));
+			}
+		}
+		let masked = render(0.3, &Dissolve::Mask { mask, softness: 0.1 });
+		assert_eq!(masked.get_pixel(12, 3)[0], 255);
+		assert_eq!(masked.get_pixel(3, 3)[0], 0);
+
+		// One point switches early and one late.
+		let local = Dissolve::Local { delays: vec![0.0, 0.5, 0.5, 1.0], radius: 4.0, softness: 0.1 };
+		let spread = render(0.5, &local);
+		assert_eq!(spread.get_pixel(0, 0)[0], 255);
+		assert_eq!(spread.get_pixel(15, 15)[0], 0);
+	}
+
+	#[test]
+	fn test_luminance_dissolve() {
+		let mut left = RgbImage::from_pixel(16, 16, Rgb([20, 20, 20]));
+		for y in 0..16 {
+			for x in 0..8 {
+				left.put_pixel(x, y, Rgb([220, 220, 220]));
+			}
+		}
+		let left = DynamicImage::ImageRgb8(left);
+		let right = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 16, Rgb([0, 0, 255])));
+		let points = corners(15.0, 15.0);
+		let settings = settings(BlendSpace::Srgb);
+		let bright_first = morph(&MorphInput { dissolve: &Dissolve::Luminance { softness: 0.05, invert: false }, ..MorphInput::new(&points, &points, &left, &right, &points) }, 0.5, &settings);
+		assert_eq!(bright_first.get_pixel(3, 3).0, [0, 0, 255, 255]);
+		assert_eq!(bright_first.get_pixel(12, 3).0, [20, 20, 20, 255]);
+		let dark_first = morph(&MorphInput { dissolve: &Dissolve::Luminance { softness: 0.05, invert: true }, ..MorphInput::new(&points, &points, &left, &right, &points) }, 0.5, &settings);
+		assert_eq!(dark_first.get_pixel(3, 3).0, [220, 220, 220, 255]);
+		assert_eq!(dark_first.get_pixel(12, 3).0, [0, 0, 255, 255]);
+	}
+
+	#[test]
+	fn test_color_match_fades_out() {
+		let left = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 16, |x, y| Rgb([(x * 10 + 50) as u8, (y * 10 + 50) as u8, 100])));
+		let right = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 16, |x, y| Rgb([(x * 5 + 10) as u8, (y * 5 + 10) as u8, 40])));
+		let points = corners(15.0, 15.0);
+		let matched = MorphSettings { color_match: ColorMatch::MeanVariance, ..settings(BlendSpace::LinearLight) };
+		let plain = settings(BlendSpace::LinearLight);
+		let brightness = |image: &RgbaImage| image.pixels().map(|p| p[0] as u32 + p[1] as u32 + p[2] as u32).sum::<u32>();
+
+		// Halfway the right image is half the blend, and still half matched, so the frame stays much closer to the left.
+		let halfway = morph(&MorphInput::new(&points, &points, &left, &right, &points), 0.5, &matched);
+		let halfway_plain = morph(&MorphInput::new(&points, &points, &left, &right, &points), 0.5, &plain);
+		let left_only = morph(&MorphInput::new(&points, &points, &left, &right, &points), 0.0, &plain);
+		let gap = |image: &RgbaImage| (brightness(image) as i64 - brightness(&left_only) as i64).abs();
+		assert!(gap(&halfway) < gap(&halfway_plain) / 2);
+
+		// At the end it's the unaltered right image.
+		let end = morph(&MorphInput::new(&points, &points, &left, &right, &points), 1.0, &matched);
+		assert_eq!(end, morph(&MorphInput::new(&points, &points, &left, &right, &points), 1.0, &plain));
+	}
+}
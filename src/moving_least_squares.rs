@@ -0,0 +1,187 @@
+// Image Deformation Using Moving Least Squares, Schaefer, McPhail, and Warren, 2006.
+// https://people.engr.tamu.edu/schaefer/research/mls.pdf
+// Unlike the thin plate spline, there's no global fit here. Every query point solves its own tiny
+// weighted least squares problem, so the per-point cost is O(n_c) and there's nothing to invert up front.
+
+use ndarray::prelude::*;
+
+/// The family of transforms which are fit locally at each point.
+/// Affine allows non-uniform scaling and shear, Similarity allows rotation and uniform scale,
+/// and Rigid allows only rotation and translation, which avoids most of the folding and stretching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum MlsMode {
+	Affine,
+	Similarity,
+	Rigid,
+}
+
+pub struct MovingLeastSquares {
+	source_points: Array2<f32>,
+	destination_points: Array2<f32>,
+	mode: MlsMode,
+	alpha: f32,
+}
+
+impl MovingLeastSquares {
+	/// Given two arrays of points in the form [x, y, x, y, x, y, ...], a deformation mode, and an alpha,
+	/// build a moving least squares deformation which maps the source points onto the destination points.
+	/// Alpha controls the falloff of the weights, w_i = 1/|p_i - v|^(2*alpha). The paper uses 1.0.
+	pub fn new(source_points: &Vec<f32>, destination_points: &Vec<f32>, mode: MlsMode, alpha: f32) -> Self {
+		assert_eq!(source_points.len() % 2, 0);
+		assert_eq!(destination_points.len() % 2, 0);
+		assert_eq!(source_points.len(), destination_points.len());
+		assert!(!source_points.is_empty());
+
+		let n_c = source_points.len() / 2;
+		Self {
+			source_points: Array2::from_shape_vec((n_c, 2), source_points.clone()).unwrap(),
+			destination_points: Array2::from_shape_vec((n_c, 2), destination_points.clone()).unwrap(),
+			mode,
+			alpha,
+		}
+	}
+
+	pub fn transform(&self, points: &Vec<f32>) -> Vec<f32> {
+		assert_eq!(points.len() % 2, 0);
+		let mut result = Vec::with_capacity(points.len());
+		for pt in points.chunks_exact(2) {
+			let (x, y) = self.transform_point(pt[0], pt[1]);
+			result.push(x);
+			result.push(y);
+		}
+		result
+	}
+
+	fn transform_point(&self, x: f32, y: f32) -> (f32, f32) {
+		// Each control point as (weight, source, destination).
+		// If we land right on a control point the weight is infinite and the answer is exactly that point's target.
+		let mut controls = Vec::with_capacity(self.source_points.nrows());
+		for (p, q) in self.source_points.outer_iter().zip(self.destination_points.outer_iter()) {
+			let dx = p[0] - x;
+			let dy = p[1] - y;
+			let dist_sq = dx*dx + dy*dy;
+			if dist_sq < 1e-10 {
+				return (q[0], q[1]);
+			}
+			controls.push((1.0f32 / dist_sq.powf(self.alpha), p, q));
+		}
+
+		// The weighted centroids p* and q*.
+		let weight_sum: f32 = controls.iter().map(|(w, _, _)| w).sum();
+		let (mut p_star_x, mut p_star_y, mut q_star_x, mut q_star_y) = (0.0f32, 0.0f32, 0.0f32, 0.0f32);
+		for &(w, p, q) in &controls {
+			p_star_x += w * p[0];
+			p_star_y += w * p[1];
+			q_star_x += w * q[0];
+			q_star_y += w * q[1];
+		}
+		p_star_x /= weight_sum;
+		p_star_y /= weight_sum;
+		q_star_x /= weight_sum;
+		q_star_y /= weight_sum;
+
+		// v - p*
+		let vx = x - p_star_x;
+		let vy = y - p_star_y;
+
+		match self.mode {
+			MlsMode::Affine => {
+				// M = (sum w p_hat^T p_hat)^-1 (sum w p_hat^T q_hat), and f(v) = (v - p*) M + q*.
+				let (mut a, mut b, mut d) = (0.0f32, 0.0f32, 0.0f32); // Symmetric 2x2: [[a, b], [b, d]]
+				let (mut m00, mut m01, mut m10, mut m11) = (0.0f32, 0.0f32, 0.0f32, 0.0f32);
+				for &(w, p, q) in &controls {
+					let px = p[0] - p_star_x;
+					let py = p[1] - p_star_y;
+					let qx = q[0] - q_star_x;
+					let qy = q[1] - q_star_y;
+					a += w*px*px;
+					b += w*px*py;
+					d += w*py*py;
+					m00 += w*px*qx;
+					m01 += w*px*qy;
+					m10 += w*py*qx;
+					m11 += w*py*qy;
+				}
+				let det = a*d - b*b;
+				if det.abs() < 1e-10 {
+					// Degenerate (e.g., all points collinear). Fall back to a translation.
+					return (vx + q_star_x, vy + q_star_y);
+				}
+				// Row vector v times inverse times M.
+				let ivx = (d*vx - b*vy) / det;
+				let ivy = (-b*vx + a*vy) / det;
+				(ivx*m00 + ivy*m10 + q_star_x, ivx*m01 + ivy*m11 + q_star_y)
+			},
+			MlsMode::Similarity | MlsMode::Rigid => {
+				// Treating points as complex numbers, the best similarity is the single complex multiplier
+				// s = sum(w conj(p_hat) q_hat) / sum(w |p_hat|^2).  Rigid is the same rotation with unit length.
+				let (mut re, mut im, mut mu) = (0.0f32, 0.0f32, 0.0f32);
+				for &(w, p, q) in &controls {
+					let px = p[0] - p_star_x;
+					let py = p[1] - p_star_y;
+					let qx = q[0] - q_star_x;
+					let qy = q[1] - q_star_y;
+					re += w*(px*qx + py*qy);
+					im += w*(px*qy - py*qx);
+					mu += w*(px*px + py*py);
+				}
+				let (sr, si) = if self.mode == MlsMode::Rigid {
+					let magnitude = (re*re + im*im).sqrt();
+					if magnitude < 1e-10 { (1.0, 0.0) } else { (re / magnitude, im / magnitude) }
+				} else {
+					if mu < 1e-10 { (1.0, 0.0) } else { (re / mu, im / mu) }
+				};
+				(sr*vx - si*vy + q_star_x, si*vx + sr*vy + q_star_y)
+			},
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_points_close(a: &Vec<f32>, b: &Vec<f32>, tolerance: f32) {
+		assert_eq!(a.len(), b.len());
+		for (x, y) in a.iter().zip(b.iter()) {
+			assert!((x - y).abs() < tolerance, "{a:?} != {b:?}");
+		}
+	}
+
+	#[test]
+	fn test_control_points_exact() {
+		let src = vec![0.0f32, 0.0, 10.0, 0.0, 0.0, 10.0, 10.0, 10.0];
+		let dst = vec![1.0f32, 2.0, 12.0, 1.0, -1.0, 9.0, 11.0, 13.0];
+		for mode in [MlsMode::Affine, MlsMode::Similarity, MlsMode::Rigid] {
+			let mls = MovingLeastSquares::new(&src, &dst, mode, 1.0);
+			assert_points_close(&mls.transform(&src), &dst, 1e-4);
+		}
+	}
+
+	#[test]
+	fn test_rigid_rotation() {
+		// Rotate a square 90 degrees around the origin.  Every mode should reproduce it everywhere.
+		let src = vec![1.0f32, 0.0, 0.0, 1.0, -1.0, 0.0, 0.0, -1.0, 2.0, 2.0];
+		let dst = vec![0.0f32, 1.0, -1.0, 0.0, 0.0, -1.0, 1.0, 0.0, -2.0, 2.0];
+		let queries = vec![0.5f32, 0.25, 3.0, -1.0, -0.7, 0.1];
+		let expected = vec![-0.25f32, 0.5, 1.0, 3.0, -0.1, -0.7];
+		for mode in [MlsMode::Affine, MlsMode::Similarity, MlsMode::Rigid] {
+			let mls = MovingLeastSquares::new(&src, &dst, mode, 1.0);
+			assert_points_close(&mls.transform(&queries), &expected, 1e-4);
+		}
+	}
+
+	#[test]
+	fn test_rigid_preserves_scale() {
+		// The destination is scaled up 2x.  Similarity should follow it, but rigid can only rotate and translate.
+		let src = vec![0.0f32, 0.0, 4.0, 0.0, 0.0, 4.0, 4.0, 4.0];
+		let dst = vec![0.0f32, 0.0, 8.0, 0.0, 0.0, 8.0, 8.0, 8.0];
+		let center = vec![2.0f32, 2.0, 3.0, 2.0];
+		let similarity = MovingLeastSquares::new(&src, &dst, MlsMode::Similarity, 1.0).transform(&center);
+		let rigid = MovingLeastSquares::new(&src, &dst, MlsMode::Rigid, 1.0).transform(&center);
+		assert_points_close(&similarity, &vec![4.0, 4.0, 6.0, 4.0], 1e-4);
+		// Off-center, the rigid fit keeps the original offset from the weighted centroid instead of doubling it.
+		assert_points_close(&rigid, &vec![4.0, 4.0, 53.0 / 9.0, 4.0], 1e-4);
+	}
+}
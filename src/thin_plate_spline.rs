@@ -4,11 +4,147 @@
 
 use ndarray::prelude::*;
 use ndarray::*;
-use ndarray_linalg::{Solve, SVD};
+use anyhow::{anyhow, bail, Result};
+use std::cell::OnceCell;
 
+use crate::linalg;
+
+/// The radial basis function used to build the spline.
+/// ThinPlate, Multiquadric and the polyharmonic family are global: moving one point moves everything a little.
+/// Gaussian and InverseMultiquadric fall off with distance, and Wendland is exactly zero beyond its radius.
+/// The shape parameter epsilon is in inverse pixels, so larger values mean more local.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum RadialBasis {
+	/// r^2 ln(r).  The classic minimum bending energy spline.
+	#[default]
+	ThinPlate,
+	/// exp(-(epsilon r)^2)
+	Gaussian { epsilon: f32 },
+	/// sqrt(1 + (epsilon r)^2)
+	Multiquadric { epsilon: f32 },
+	/// 1 / sqrt(1 + (epsilon r)^2)
+	InverseMultiquadric { epsilon: f32 },
+	/// Wendland's C2 function, (1 - r/radius)^4 (4r/radius + 1), and zero for r >= radius.
+	Wendland { radius: f32 },
+}
+
+impl RadialBasis {
+	pub fn evaluate(&self, r: f32) -> f32 {
+		match *self {
+			RadialBasis::ThinPlate => {
+				if r > 1e-5 {
+					(r*r)*r.ln()
+				} else {
+					0.0f32
+				}
+			},
+			RadialBasis::Gaussian { epsilon } => (-(epsilon*r)*(epsilon*r)).exp(),
+			RadialBasis::Multiquadric { epsilon } => (1.0 + (epsilon*r)*(epsilon*r)).sqrt(),
+			RadialBasis::InverseMultiquadric { epsilon } => 1.0 / (1.0 + (epsilon*r)*(epsilon*r)).sqrt(),
+			RadialBasis::Wendland { radius } => {
+				let q = r / radius;
+				if q >= 1.0 {
+					0.0f32
+				} else {
+					(1.0 - q).powi(4) * (4.0*q + 1.0)
+				}
+			},
+		}
+	}
+
+	/// The same kernel, with its shape parameter converted for distances measured in units `scale` times larger.
+	pub fn rescaled(&self, scale: f32) -> RadialBasis {
+		match *self {
+			RadialBasis::ThinPlate => RadialBasis::ThinPlate,
+			RadialBasis::Gaussian { epsilon } => RadialBasis::Gaussian { epsilon: epsilon * scale },
+			RadialBasis::Multiquadric { epsilon } => RadialBasis::Multiquadric { epsilon: epsilon * scale },
+			RadialBasis::InverseMultiquadric { epsilon } => RadialBasis::InverseMultiquadric { epsilon: epsilon * scale },
+			RadialBasis::Wendland { radius } => RadialBasis::Wendland { radius: radius / scale },
+		}
+	}
+
+	/// The radial derivative divided by r, phi'(r) / r.
+	/// The gradient of phi(|x - c|) with respect to x is this times (x - c), which is how the Jacobian uses it.
+	pub fn derivative_over_r(&self, r: f32) -> f32 {
+		match *self {
+			RadialBasis::ThinPlate => {
+				// d/dr r^2 ln(r) = 2r ln(r) + r.  At r = 0 this is log-singular, but it's multiplied by (x - c) = 0.
+				if r > 1e-5 {
+					2.0*r.ln() + 1.0
+				} else {
+					0.0f32
+				}
+			},
+			RadialBasis::Gaussian { epsilon } => -2.0*epsilon*epsilon*self.evaluate(r),
+			RadialBasis::Multiquadric { epsilon } => epsilon*epsilon / self.evaluate(r),
+			RadialBasis::InverseMultiquadric { epsilon } => -epsilon*epsilon*self.evaluate(r).powi(3),
+			RadialBasis::Wendland { radius } => {
+				let q = r / radius;
+				if q >= 1.0 {
+					0.0f32
+				} else {
+					-20.0*(1.0 - q).powi(3) / (radius*radius)
+				}
+			},
+		}
+	}
+}
+
+const INVERSE_MAX_ITERATIONS: usize = 20;
+const INVERSE_TOLERANCE: f32 = 1e-3;
+// Candidates for the automatic alpha search, in normalized units.  Zero is pure interpolation.
+const ALPHA_CANDIDATES: [f32; 10] = [0.0, 1e-6, 1e-5, 1e-4, 1e-3, 1e-2, 1e-1, 1.0, 10.0, 100.0];
+// Bump this whenever the meaning of a field in SerializedSpline changes.
+const SERIALIZED_VERSION: u32 = 1;
+
+/// Control points are moved to be centered on the origin with unit RMS distance before fitting.
+/// That makes alpha mean the same thing on a 256px image as on a 4K one, and it keeps the system well conditioned.
+/// The scale is shared by every axis so distances, and with them the radial basis, aren't distorted.
+#[derive(Debug, Clone)]
+struct Normalization {
+	center: Array1<f32>,
+	scale: f32,
+}
+
+impl Normalization {
+	fn fit(points: ArrayView2<f32>) -> Self {
+		let center = points.mean_axis(Axis(0)).expect("Can't normalize an empty set of points.");
+		let mean_square = (&points - &center).mapv(|v| v*v).sum() / points.nrows() as f32;
+		let scale = if mean_square > 1e-12 { mean_square.sqrt() } else { 1.0 };
+		Self { center, scale }
+	}
+
+	fn apply(&self, points: ArrayView2<f32>) -> Array2<f32> {
+		(&points - &self.center) / self.scale
+	}
+
+	fn apply_point(&self, point: &[f32]) -> Array1<f32> {
+		(&ArrayView1::from(point) - &self.center) / self.scale
+	}
+
+	fn invert(&self, points: ArrayView2<f32>) -> Array2<f32> {
+		&points * self.scale + &self.center
+	}
+}
+
+/// Serializes as a `SerializedSpline`, so the layout on disk doesn't follow the internals around.
+/// The cached system inverse and inverse fit aren't saved.  They're rebuilt the first time they're needed after loading.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+#[serde(into = "SerializedSpline", try_from = "SerializedSpline")]
 pub struct ThinPlateSpline {
 	parameters: Array2<f32>,
+	// Control points and the kernel are both in normalized units.  Targets are left alone.
 	control_points: Array2<f32>,
+	target_points: Array2<f32>,
+	// Per-point confidence.  The regularization on point i is alpha / weight_i.
+	weights: Array1<f32>,
+	kernel: RadialBasis,
+	normalization: Normalization,
+	alpha: f32,
+	// Inverse of the system matrix, cached for incremental updates.  Built on the first edit.
+	system_inverse: Option<Array2<f64>>,
+	// The swapped fit `apply_inverse` starts from, kept until the control points change.
+	inverse_fit: OnceCell<Box<ThinPlateSpline>>,
 }
 
 impl ThinPlateSpline {
@@ -17,65 +153,612 @@ impl ThinPlateSpline {
 	/// compute the thin-plate-spline solution and return an instance of the structure.
 	/// Note that the order of x,y in the vec doesn't really matter as long as it's consistent and
 	/// each point is contiguous.
+	/// Alpha is applied after the source points are normalized to unit scale, so it doesn't depend on the image size.
+	/// Zero interpolates the points exactly and larger values smooth more.
 	pub fn new(source_points: &Vec<f32>, destination_points: &Vec<f32>, alpha: f32) -> Self {
-		assert_eq!(source_points.len() % 2, 0);
-		assert_eq!(destination_points.len() % 2, 0);
-		assert!(source_points.len() > 3);
-		assert!(destination_points.len() > 3);
-		assert_eq!(source_points.len(), destination_points.len());
-
-		let source_mat = vec_to_mat(source_points, source_points.len()/2, 2);
-		let dest_mat = vec_to_mat(destination_points, destination_points.len()/2, 2);
-
-		let n_c = source_points.len() / 2;
-		let d_s = 2; // We're always assuming (x, y) for each point, but this could generalize.
-
-		let radial_distances = compute_radial_distances(&source_mat, &dest_mat);
-
-		// Build K, X'_c, X'_c^T, and 0.
-		// Build A from the above.
-		// Build AP = Y and solve.
-
-		// Top left, K
-		let K = radial_distances + (Array2::<f32>::eye(n_c) * alpha);
-		// Top right, [ 1 | source ] or X_p
-		let X_p = concatenate(Axis(1), &[(&Array2::ones((n_c, 1))).into(), (&source_mat).into()]).expect("Source dimension mismatch for source points.");
-		// Bottom left: X_p.T
-		let X_p_t = X_p.t(); // .reversed_axes for by-move.
-		// Bottom right:
-		let zeros = Array2::zeros((d_s + 1, d_s + 1));
-		let A_top = concatenate(Axis(1), &[(&K).into(), (&X_p).into()]).unwrap(); // hstack
-		let A_bottom = concatenate(Axis(1), &[(&X_p_t).into(), (&zeros).into()]).unwrap();
-		let A = concatenate(Axis(0), &[(&A_top).into(), (&A_bottom).into()]).unwrap(); // vstack
-
-		// Build Y from the destinations and some zero fills.
-		let target_zeros = Array2::zeros((d_s + 1, dest_mat.ncols()));
-		let y = concatenate(Axis(0), &[(&dest_mat).into(), (&target_zeros).into()]).unwrap();
-
-		let parameters = least_squares(&A, &y).unwrap();
+		Self::new_with_kernel(source_points, destination_points, alpha, RadialBasis::ThinPlate)
+	}
+
+	/// As `new`, but with a radial basis other than the thin plate kernel.
+	pub fn new_with_kernel(source_points: &Vec<f32>, destination_points: &Vec<f32>, alpha: f32, kernel: RadialBasis) -> Self {
+		Self::fit(points_view(source_points), points_view(destination_points), Some(alpha), kernel, None)
+	}
+
+	/// As `new_with_kernel`, but picks alpha automatically with `select_alpha`.
+	pub fn new_with_automatic_alpha(source_points: &Vec<f32>, destination_points: &Vec<f32>, kernel: RadialBasis) -> Self {
+		Self::fit(points_view(source_points), points_view(destination_points), None, kernel, None)
+	}
+
+	/// Fit a spline which maps each row of `source` onto the same row of `target`.
+	/// The two can have any number of columns and needn't match, so this works just as well for 3D volumes
+	/// or for (x, y, t) -> (x, y) spatio-temporal warps.  (Strictly, r^2 ln(r) is only the minimum bending energy
+	/// kernel in 2D, but it's a valid interpolant in any dimension.)
+	/// If alpha is None it's picked with `select_alpha`.
+	/// Weights are an optional per-point confidence, all positive.  Point i is regularized by alpha / weight_i,
+	/// so a heavily weighted point is followed closely and a lightly weighted one can be pulled toward its neighbors.
+	/// With alpha = 0 every point is interpolated exactly and the weights don't matter.
+	pub fn fit(source: ArrayView2<f32>, target: ArrayView2<f32>, alpha: Option<f32>, kernel: RadialBasis, weights: Option<ArrayView1<f32>>) -> Self {
+		assert_eq!(source.nrows(), target.nrows());
+		assert!(source.nrows() > 1);
+		assert!(source.ncols() > 0 && target.ncols() > 0);
+		let weights = match weights {
+			Some(w) => {
+				assert_eq!(w.len(), source.nrows());
+				assert!(w.iter().all(|v| *v > 0.0), "Control point weights must be positive.");
+				w.to_owned()
+			},
+			None => Array1::ones(source.nrows()),
+		};
+		let alpha = alpha.unwrap_or_else(|| Self::select_alpha(source, target, kernel, Some(weights.view())));
+
+		let normalization = Normalization::fit(source);
+		let control_points = normalization.apply(source);
+		let kernel = kernel.rescaled(normalization.scale);
+		let target_points = target.to_owned();
+
+		let a = build_system(&control_points, &kernel, alpha, &weights);
+		let y = build_targets(&target_points, control_points.ncols());
+		let parameters = solve_system(&a, &y);
 
 		Self {
 			parameters,
-			control_points: source_mat.into(),
+			control_points,
+			target_points,
+			weights,
+			kernel,
+			normalization,
+			alpha,
+			system_inverse: None,
+			inverse_fit: OnceCell::new(),
+		}
+	}
+
+	/// Pick the regularization by generalized cross-validation, the alpha which best predicts each point from all the others.
+	/// Clean, consistent correspondences come out at or near zero.  Noisy or contradictory ones get smoothed.
+	pub fn select_alpha(source: ArrayView2<f32>, target: ArrayView2<f32>, kernel: RadialBasis, weights: Option<ArrayView1<f32>>) -> f32 {
+		let n_c = source.nrows();
+		let normalization = Normalization::fit(source);
+		let source_mat = normalization.apply(source);
+		let kernel = kernel.rescaled(normalization.scale);
+		let weights = weights.map(|w| w.to_owned()).unwrap_or_else(|| Array1::ones(n_c));
+		let y = target.mapv(|v| v as f64);
+
+		let mut best_alpha = 0.0f32;
+		let mut best_score = f64::INFINITY;
+		for alpha in ALPHA_CANDIDATES {
+			let a = build_system(&source_mat, &kernel, alpha, &weights).mapv(|v| v as f64);
+			let inverse = match linalg::lu_solve(&a, &Array2::eye(a.nrows())) {
+				Ok(inverse) => inverse,
+				Err(_) => continue,
+			};
+			// The residual at the control points is alpha W^-1 C, where C = (A^-1)_nn Y are the kernel weights,
+			// and the trace of the residual operator is alpha tr(W^-1 (A^-1)_nn).  The alphas cancel in the GCV score,
+			// GCV = n |W^1/2 alpha W^-1 C|^2 / tr(alpha W^-1 (A^-1)_nn)^2, which is what makes alpha = 0 a valid candidate.
+			let inverse_nn = inverse.slice(s![..n_c, ..n_c]);
+			let coefficients = inverse_nn.dot(&y);
+			let mut residual = 0.0f64;
+			let mut trace = 0.0f64;
+			for i in 0..n_c {
+				let w = weights[i] as f64;
+				residual += coefficients.row(i).mapv(|v| v*v).sum() / w;
+				trace += inverse_nn[(i, i)] / w;
+			}
+			let score = n_c as f64 * residual / (trace*trace);
+			if score.is_finite() && score < best_score {
+				best_score = score;
+				best_alpha = alpha;
+			}
+		}
+		best_alpha
+	}
+
+	pub fn get_alpha(&self) -> f32 {
+		self.alpha
+	}
+
+	pub fn get_num_control_points(&self) -> usize {
+		self.control_points.nrows()
+	}
+
+	pub fn get_source_dimensions(&self) -> usize {
+		self.control_points.ncols()
+	}
+
+	pub fn get_target_dimensions(&self) -> usize {
+		self.target_points.ncols()
+	}
+
+	/// Export the fitted spline as JSON.  Everything needed to apply it is included, so a cached fit can be reused
+	/// on other images with the same layout, or evaluated by another tool using the layout in `SerializedSpline`.
+	pub fn to_json(&self) -> Result<String> {
+		Ok(serde_json::to_string_pretty(self)?)
+	}
+
+	pub fn from_json(json: &str) -> Result<Self> {
+		Ok(serde_json::from_str(json)?)
+	}
+
+	/// Export the fitted spline in a compact binary form, the same fields as `to_json` in little-endian bincode.
+	pub fn to_bytes(&self) -> Result<Vec<u8>> {
+		Ok(bincode::serialize(self)?)
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+		Ok(bincode::deserialize(bytes)?)
+	}
+
+	/// Move a single control point, either its source position, its destination, or both, without a complete refit.
+	/// Either way this is O(n^2) once the inverse is cached: moving the source is a rank-two update of the inverse,
+	/// and the parameters are then recomputed from it with a dense product.
+	/// The first incremental edit builds that inverse, which costs about as much as a fresh fit.
+	/// Incremental edits keep the normalization from the original fit, so with alpha > 0 the result can differ
+	/// very slightly from fitting the edited points from scratch.
+	pub fn move_control_point(&mut self, idx: usize, source: Option<&[f32]>, destination: Option<&[f32]>) {
+		let n_c = self.get_num_control_points();
+		assert!(idx < n_c, "Control point {idx} doesn't exist.  The spline has {n_c}.");
+		self.inverse_fit.take();
+		let source = source.map(|p| self.normalization.apply_point(p));
+		if !self.ensure_inverse() {
+			if let Some(p) = &source {
+				self.control_points.row_mut(idx).assign(p);
+			}
+			if let Some(p) = destination {
+				self.target_points.row_mut(idx).assign(&ArrayView1::from(p));
+			}
+			self.refit();
+			return;
+		}
+
+		if let Some(p) = source {
+			// Only row and column idx of the system change: dA = e_i u^T + u e_i^T.  The diagonal stays phi(0) + alpha/w_i.
+			let old_row = self.system_row(self.control_points.row(idx), Some(idx));
+			self.control_points.row_mut(idx).assign(&p);
+			let mut u = self.system_row(p.view(), Some(idx)) - old_row;
+			u[idx] = 0.0;
+
+			// Woodbury with U = [e_i, u] and V = [u, e_i].  The inverse is symmetric so u^T B = (B u)^T.
+			let b = self.system_inverse.as_ref().unwrap();
+			let b_u = b.dot(&u);
+			let b_i = b.column(idx).to_owned();
+			let c00 = 1.0 + b_u[idx];
+			let c01 = u.dot(&b_u);
+			let c10 = b[(idx, idx)];
+			let c11 = 1.0 + b_u[idx];
+			let det = c00*c11 - c01*c10;
+			if det.abs() < 1e-12 {
+				self.system_inverse = None;
+				self.refit();
+				return;
+			}
+			// B' = B - [B e_i, B u] C^-1 [u^T B; e_i^T B]
+			let left_0 = (&b_i * c11 - &b_u * c10) / det;
+			let left_1 = (&b_u * c00 - &b_i * c01) / det;
+			let update = outer(&left_0, &b_u) + outer(&left_1, &b_i);
+			*self.system_inverse.as_mut().unwrap() -= &update;
+		}
+		if let Some(p) = destination {
+			self.target_points.row_mut(idx).assign(&ArrayView1::from(p));
 		}
+		self.update_parameters();
+	}
+
+	/// Append a new control point, with a weight of one, without a complete refit.  O(n^2) once the inverse is cached.
+	/// The new point takes the last index.
+	pub fn add_control_point(&mut self, source: &[f32], destination: &[f32]) {
+		self.inverse_fit.take();
+		let source = self.normalization.apply_point(source);
+		let n_c = self.get_num_control_points();
+		let control_points = concatenate(Axis(0), &[self.control_points.view(), source.view().insert_axis(Axis(0))]).unwrap();
+		let target_points = concatenate(Axis(0), &[self.target_points.view(), ArrayView1::from(destination).insert_axis(Axis(0))]).unwrap();
+		let weights = concatenate(Axis(0), &[self.weights.view(), ArrayView1::from(&[1.0f32])]).unwrap();
+
+		if !self.ensure_inverse() {
+			self.control_points = control_points;
+			self.target_points = target_points;
+			self.weights = weights;
+			self.refit();
+			return;
+		}
+
+		// Grow the system by one row and column at the end, [[A, b], [b^T, d]], and use the block inverse.
+		// Then shuffle the new row/column in front of the polynomial rows, which is where it lives in the layout.
+		let b = self.system_row(source.view(), None);
+		let d = self.kernel.evaluate(0.0) as f64 + self.alpha as f64;
+		let inverse = self.system_inverse.as_ref().unwrap();
+		let inverse_b = inverse.dot(&b);
+		let schur = d - b.dot(&inverse_b);
+		if schur.abs() < 1e-12 {
+			self.control_points = control_points;
+			self.target_points = target_points;
+			self.weights = weights;
+			self.system_inverse = None;
+			self.refit();
+			return;
+		}
+		let size = inverse.nrows();
+		let mut grown = Array2::<f64>::zeros((size + 1, size + 1));
+		grown.slice_mut(s![..size, ..size]).assign(&(inverse + &(outer(&inverse_b, &inverse_b) / schur)));
+		grown.slice_mut(s![..size, size]).assign(&(&inverse_b / -schur));
+		grown.slice_mut(s![size, ..size]).assign(&(&inverse_b / -schur));
+		grown[(size, size)] = 1.0 / schur;
+		let order: Vec<usize> = (0..n_c).chain(std::iter::once(size)).chain(n_c..size).collect();
+		self.system_inverse = Some(grown.select(Axis(0), &order).select(Axis(1), &order));
+
+		self.control_points = control_points;
+		self.target_points = target_points;
+		self.weights = weights;
+		self.update_parameters();
+	}
+
+	/// Remove a control point without a complete refit.  O(n^2) once the inverse is cached.
+	/// Points after idx shift down by one.
+	pub fn remove_control_point(&mut self, idx: usize) {
+		let n_c = self.get_num_control_points();
+		assert!(idx < n_c, "Control point {idx} doesn't exist.  The spline has {n_c}.");
+		assert!(n_c > 2, "A spline needs at least two control points.");
+		self.inverse_fit.take();
+		let keep: Vec<usize> = (0..n_c).filter(|&i| i != idx).collect();
+		let control_points = self.control_points.select(Axis(0), &keep);
+		let target_points = self.target_points.select(Axis(0), &keep);
+		let weights = self.weights.select(Axis(0), &keep);
+
+		if !self.ensure_inverse() {
+			self.control_points = control_points;
+			self.target_points = target_points;
+			self.weights = weights;
+			self.refit();
+			return;
+		}
+
+		// Move idx to the end, then the inverse of the leading block is B_rr - B_ri B_ir / B_ii.
+		let inverse = self.system_inverse.as_ref().unwrap();
+		let size = inverse.nrows();
+		let order: Vec<usize> = (0..size).filter(|&i| i != idx).chain(std::iter::once(idx)).collect();
+		let permuted = inverse.select(Axis(0), &order).select(Axis(1), &order);
+		let pivot = permuted[(size - 1, size - 1)];
+		if pivot.abs() < 1e-12 {
+			self.control_points = control_points;
+			self.target_points = target_points;
+			self.weights = weights;
+			self.system_inverse = None;
+			self.refit();
+			return;
+		}
+		let column = permuted.slice(s![..size - 1, size - 1]).to_owned();
+		let row = permuted.slice(s![size - 1, ..size - 1]).to_owned();
+		let shrunk = &permuted.slice(s![..size - 1, ..size - 1]) - &(outer(&column, &row) / pivot);
+		self.system_inverse = Some(shrunk);
+
+		self.control_points = control_points;
+		self.target_points = target_points;
+		self.weights = weights;
+		self.update_parameters();
+	}
+
+	/// Make sure the system inverse is cached.  Returns false if the system is singular and can't be inverted,
+	/// in which case the incremental updates fall back to a complete refit.
+	fn ensure_inverse(&mut self) -> bool {
+		if self.system_inverse.is_none() {
+			let a = build_system(&self.control_points, &self.kernel, self.alpha, &self.weights).mapv(|v| v as f64);
+			if let Ok(inverse) = linalg::lu_solve(&a, &Array2::eye(a.nrows())) {
+				if inverse.iter().all(|v| v.is_finite()) && (a.dot(&inverse) - Array2::<f64>::eye(a.nrows())).mapv(f64::abs).sum() < 1e-6 * a.nrows() as f64 {
+					self.system_inverse = Some(inverse);
+				}
+			}
+		}
+		self.system_inverse.is_some()
+	}
+
+	/// One row of the system matrix for a (normalized) point against the current control points.
+	/// If the point is itself control point `own_idx`, the regularization is added on the diagonal.
+	fn system_row(&self, point: ArrayView1<f32>, own_idx: Option<usize>) -> Array1<f64> {
+		let n_c = self.get_num_control_points();
+		let d_s = self.get_source_dimensions();
+		let mut row = Array1::<f64>::zeros(n_c + d_s + 1);
+		for j in 0..n_c {
+			let r = if Some(j) == own_idx {
+				0.0
+			} else {
+				self.control_points.row(j).iter().zip(point.iter()).map(|(a, b)| (a - b)*(a - b)).sum::<f32>().sqrt()
+			};
+			row[j] = self.kernel.evaluate(r) as f64;
+		}
+		if let Some(i) = own_idx {
+			row[i] += (self.alpha / self.weights[i]) as f64;
+		}
+		row[n_c] = 1.0;
+		for k in 0..d_s {
+			row[n_c + 1 + k] = point[k] as f64;
+		}
+		row
+	}
+
+	fn update_parameters(&mut self) {
+		let y = build_targets(&self.target_points, self.get_source_dimensions()).mapv(|v| v as f64);
+		self.parameters = self.system_inverse.as_ref().unwrap().dot(&y).mapv(|v| v as f32);
+	}
+
+	fn refit(&mut self) {
+		let a = build_system(&self.control_points, &self.kernel, self.alpha, &self.weights);
+		let y = build_targets(&self.target_points, self.get_source_dimensions());
+		self.parameters = solve_system(&a, &y);
+	}
+
+	/// Map each row of `points` from the source space into the target space.
+	pub fn apply(&self, points: ArrayView2<f32>) -> Array2<f32> {
+		assert_eq!(points.ncols(), self.get_source_dimensions());
+		let pts = self.normalization.apply(points);
+		let phi = compute_radial_distances(&self.control_points, &pts, &self.kernel);
+		let augmented = concatenate(Axis(1), &[(&phi).into(), (&Array2::ones((pts.nrows(), 1))).into(), (&pts).into()]).expect("Shape mismatch in concatenated matrix.");
+		augmented.dot(&self.parameters)
 	}
 
 	pub fn transform(&self, points: &Vec<f32>) -> Vec<f32> {
-		let pts = vec_to_mat(points, points.len()/2, 2);
-		let phi = compute_radial_distances(&self.control_points, &pts);
-		let augmented = concatenate(Axis(1), &[(&phi).into(), (&Array2::ones((pts.shape()[0], 1))).into(), (&pts).into()]).expect("Shape mismatch in concatenated matrix.");
-		let result = augmented.dot(&self.parameters);
-		result.into_raw_vec()
+		self.apply(points_view(points)).into_raw_vec()
+	}
+
+	/// Fit the spline going the other way, destination to source, with the same kernel, alpha and weights.
+	/// This is exact at the control points (for alpha = 0) but only approximately the inverse in between.
+	/// Use `transform_inverse` if you need the true inverse of this spline.
+	pub fn inverse(&self) -> ThinPlateSpline {
+		let source_points = self.normalization.invert(self.control_points.view());
+		let kernel = self.kernel.rescaled(1.0 / self.normalization.scale);
+		ThinPlateSpline::fit(self.target_points.view(), source_points.view(), Some(self.alpha), kernel, Some(self.weights.view()))
+	}
+
+	/// Map each row of `points` from the target space back to the source space, numerically inverting this spline.
+	/// Only possible when both spaces have the same number of dimensions.
+	/// Starts from the approximate `inverse` fit and refines each point with Newton's method.
+	/// That fit is made on the first call and reused until the control points are edited.
+	/// Where the warp folds over itself there's no unique answer and this returns whichever preimage it lands on.
+	pub fn apply_inverse(&self, points: ArrayView2<f32>) -> Array2<f32> {
+		assert_eq!(self.get_source_dimensions(), self.get_target_dimensions(), "Only a square warp can be inverted.");
+		let mut guess = self.inverse_fit.get_or_init(|| Box::new(self.inverse())).apply(points);
+		for _ in 0..INVERSE_MAX_ITERATIONS {
+			let forward = self.apply(guess.view());
+			let residual = &points - &forward;
+			if residual.iter().all(|v| v.abs() < INVERSE_TOLERANCE) {
+				break;
+			}
+			let jacobian = self.jacobian_matrices(guess.view());
+			for (i, mut point) in guess.rows_mut().into_iter().enumerate() {
+				// Solve J delta = target - f(guess).
+				if let Some(delta) = solve_small(jacobian.index_axis(Axis(0), i), residual.row(i)) {
+					point += &delta;
+				}
+			}
+		}
+		guess
+	}
+
+	pub fn transform_inverse(&self, points: &Vec<f32>) -> Vec<f32> {
+		self.apply_inverse(points_view(points)).into_raw_vec()
+	}
+
+	/// The analytic Jacobian of the transform at each row of `points`.
+	/// The result has shape (n, target dimensions, source dimensions), so [i, t, s] is d target_t / d source_s at point i.
+	pub fn jacobian_matrices(&self, points: ArrayView2<f32>) -> Array3<f32> {
+		let n_c = self.get_num_control_points();
+		let d_s = self.get_source_dimensions();
+		let d_t = self.get_target_dimensions();
+		let pts = self.normalization.apply(points);
+		let mut result = Array3::zeros((pts.nrows(), d_t, d_s));
+		for (pt, mut j) in pts.rows().into_iter().zip(result.outer_iter_mut()) {
+			// The affine part contributes its linear coefficients, rows n_c+1 onward of the parameters.
+			for t in 0..d_t {
+				for k in 0..d_s {
+					j[(t, k)] = self.parameters[(n_c + 1 + k, t)];
+				}
+			}
+			for c in 0..n_c {
+				let r = self.control_points.row(c).iter().zip(pt.iter()).map(|(a, b)| (a - b)*(a - b)).sum::<f32>().sqrt();
+				let g = self.kernel.derivative_over_r(r);
+				if g == 0.0 {
+					continue;
+				}
+				for t in 0..d_t {
+					for k in 0..d_s {
+						j[(t, k)] += self.parameters[(c, t)] * g * (pt[k] - self.control_points[(c, k)]);
+					}
+				}
+			}
+		}
+		// Chain rule through the normalization.
+		result / self.normalization.scale
+	}
+
+	/// The analytic Jacobian of the transform at each point, four values per point in the order
+	/// [dx'/dx, dx'/dy, dy'/dx, dy'/dy].  Only for 2D to 2D splines; use `jacobian_matrices` for anything else.
+	pub fn jacobian(&self, points: &Vec<f32>) -> Vec<f32> {
+		assert_eq!((self.get_source_dimensions(), self.get_target_dimensions()), (2, 2), "The flat Jacobian is only defined for 2D splines.");
+		self.jacobian_matrices(points_view(points)).into_raw_vec()
+	}
+
+	/// The determinant of the Jacobian at each point.  Zero or negative means the warp folds over itself there.
+	/// Only for 2D to 2D splines, like `jacobian`.
+	pub fn jacobian_determinant(&self, points: &Vec<f32>) -> Vec<f32> {
+		self.jacobian(points).chunks_exact(4).map(|j| j[0]*j[3] - j[1]*j[2]).collect()
+	}
+
+	/// Push the points forward and back through `transform_inverse`, returning the largest distance from where they started.
+	/// Anything more than a fraction of a pixel means the warp isn't invertible there, usually because it folds.
+	pub fn round_trip_error(&self, points: &Vec<f32>) -> f32 {
+		let round_trip = self.transform_inverse(&self.transform(points));
+		round_trip.chunks_exact(2).zip(points.chunks_exact(2)).map(|(a, b)| {
+			((a[0] - b[0])*(a[0] - b[0]) + (a[1] - b[1])*(a[1] - b[1])).sqrt()
+		}).fold(0.0f32, f32::max)
 	}
 }
 
-fn vec_to_mat(points: &Vec<f32>, num_rows: usize, num_columns: usize) -> Array2<f32> {
-	Array2::from_shape_fn((num_rows, num_columns), |(i, j)| {
-		points[num_columns*i + j]
-	})
+/// The stable, exported form of a fitted `ThinPlateSpline`.
+/// Matrices are flattened row-major.  With n control points, d_s source and d_t target dimensions, a point x maps to
+/// y_t = sum_i parameters[i][t] phi(|x' - control_i|) + parameters[n][t] + sum_k parameters[n+1+k][t] x'_k,
+/// where x' = (x - center) / scale and phi is `kernel` (whose shape parameter is already in normalized units).
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SerializedSpline {
+	pub version: u32,
+	pub source_dimensions: usize,
+	pub target_dimensions: usize,
+	pub kernel: RadialBasis,
+	pub alpha: f32,
+	pub center: Vec<f32>,
+	pub scale: f32,
+	/// (n, d_s), normalized.
+	pub control_points: Vec<f32>,
+	/// (n, d_t), in the original units.
+	pub target_points: Vec<f32>,
+	/// n
+	pub weights: Vec<f32>,
+	/// (n + 1 + d_s, d_t)
+	pub parameters: Vec<f32>,
+}
+
+impl From<ThinPlateSpline> for SerializedSpline {
+	fn from(tps: ThinPlateSpline) -> Self {
+		SerializedSpline {
+			version: SERIALIZED_VERSION,
+			source_dimensions: tps.get_source_dimensions(),
+			target_dimensions: tps.get_target_dimensions(),
+			kernel: tps.kernel,
+			alpha: tps.alpha,
+			center: tps.normalization.center.to_vec(),
+			scale: tps.normalization.scale,
+			control_points: tps.control_points.iter().cloned().collect(),
+			target_points: tps.target_points.iter().cloned().collect(),
+			weights: tps.weights.to_vec(),
+			parameters: tps.parameters.iter().cloned().collect(),
+		}
+	}
+}
+
+impl TryFrom<SerializedSpline> for ThinPlateSpline {
+	type Error = anyhow::Error;
+
+	fn try_from(data: SerializedSpline) -> Result<Self> {
+		if data.version != SERIALIZED_VERSION {
+			bail!("Unsupported spline version {}, expected {}.", data.version, SERIALIZED_VERSION);
+		}
+		let d_s = data.source_dimensions;
+		let d_t = data.target_dimensions;
+		let n_c = data.weights.len();
+		if data.center.len() != d_s {
+			bail!("Spline center has {} dimensions, expected {}.", data.center.len(), d_s);
+		}
+		if !data.scale.is_finite() || data.scale <= 0.0 {
+			bail!("Spline scale must be positive.");
+		}
+		let shape_error = |name: &str| anyhow!("Spline {} don't match {} control points in {} -> {} dimensions.", name, n_c, d_s, d_t);
+		let control_points = Array2::from_shape_vec((n_c, d_s), data.control_points).map_err(|_| shape_error("control points"))?;
+		let target_points = Array2::from_shape_vec((n_c, d_t), data.target_points).map_err(|_| shape_error("target points"))?;
+		let parameters = Array2::from_shape_vec((n_c + 1 + d_s, d_t), data.parameters).map_err(|_| shape_error("parameters"))?;
+		Ok(ThinPlateSpline {
+			parameters,
+			control_points,
+			target_points,
+			weights: Array1::from(data.weights),
+			kernel: data.kernel,
+			normalization: Normalization { center: Array1::from(data.center), scale: data.scale },
+			alpha: data.alpha,
+			system_inverse: None,
+			inverse_fit: OnceCell::new(),
+		})
+	}
+}
+
+fn build_system(source_mat: &Array2<f32>, kernel: &RadialBasis, alpha: f32, weights: &Array1<f32>) -> Array2<f32> {
+	let n_c = source_mat.nrows();
+	let d_s = source_mat.ncols();
+
+	let radial_distances = compute_radial_distances(source_mat, source_mat, kernel);
+
+	// Build K, X'_c, X'_c^T, and 0.
+	// Build A from the above.
+	// Build AP = Y and solve.
+
+	// Top left, K.  The regularization is alpha / weight on the diagonal.
+	let K = radial_distances + Array2::from_diag(&weights.mapv(|w| alpha / w));
+	// Top right, [ 1 | source ] or X_p
+	let X_p = concatenate(Axis(1), &[(&Array2::ones((n_c, 1))).into(), source_mat.into()]).expect("Source dimension mismatch for source points.");
+	// Bottom left: X_p.T
+	let X_p_t = X_p.t(); // .reversed_axes for by-move.
+	// Bottom right:
+	let zeros = Array2::zeros((d_s + 1, d_s + 1));
+	let A_top = concatenate(Axis(1), &[(&K).into(), (&X_p).into()]).unwrap(); // hstack
+	let A_bottom = concatenate(Axis(1), &[(&X_p_t).into(), (&zeros).into()]).unwrap();
+	concatenate(Axis(0), &[(&A_top).into(), (&A_bottom).into()]).unwrap() // vstack
+}
+
+fn build_targets(dest_mat: &Array2<f32>, d_s: usize) -> Array2<f32> {
+	// Build Y from the destinations and some zero fills.
+	let target_zeros = Array2::zeros((d_s + 1, dest_mat.ncols()));
+	concatenate(Axis(0), &[dest_mat.into(), (&target_zeros).into()]).unwrap()
+}
+
+fn outer(a: &Array1<f64>, b: &Array1<f64>) -> Array2<f64> {
+	Array2::from_shape_fn((a.len(), b.len()), |(i, j)| a[i] * b[j])
+}
+
+/// Solve a tiny square system by Gaussian elimination with partial pivoting.  None if it's singular.
+fn solve_small(a: ArrayView2<f32>, b: ArrayView1<f32>) -> Option<Array1<f32>> {
+	let n = b.len();
+	let mut m = a.mapv(|v| v as f64);
+	let mut x = b.mapv(|v| v as f64);
+	for c in 0..n {
+		let pivot = (c..n).max_by(|&i, &j| m[(i, c)].abs().total_cmp(&m[(j, c)].abs()))?;
+		if m[(pivot, c)].abs() < 1e-12 {
+			return None;
+		}
+		for k in 0..n {
+			m.swap((pivot, k), (c, k));
+		}
+		x.swap(pivot, c);
+		for r in (c + 1)..n {
+			let f = m[(r, c)] / m[(c, c)];
+			for k in c..n {
+				m[(r, k)] -= f * m[(c, k)];
+			}
+			x[r] -= f * x[c];
+		}
+	}
+	for c in (0..n).rev() {
+		let mut acc = x[c];
+		for k in (c + 1)..n {
+			acc -= m[(c, k)] * x[k];
+		}
+		x[c] = acc / m[(c, c)];
+	}
+	Some(x.mapv(|v| v as f32))
 }
 
-fn least_squares(a: &Array2<f32>, b: &Array2<f32>) -> Result<Array2<f32>, ndarray_linalg::error::LinalgError> {
+/// View a flat [x, y, x, y, ...] vec as an (n, 2) matrix without copying.
+fn points_view(points: &[f32]) -> ArrayView2<'_, f32> {
+	assert_eq!(points.len() % 2, 0);
+	ArrayView2::from_shape((points.len() / 2, 2), points).unwrap()
+}
+
+/// Solve the (square) spline system, preferring a direct LU solve and falling back to SVD least squares
+/// when the system is singular or badly conditioned, e.g., when two control points coincide.
+fn solve_system(a: &Array2<f32>, b: &Array2<f32>) -> Array2<f32> {
+	if let Ok(x) = direct_solve(a, b) {
+		// LU will happily return garbage for a nearly singular matrix, so check that the answer actually solves the system.
+		let residual = (a.dot(&x) - b).mapv(f32::abs).sum();
+		let scale = b.mapv(f32::abs).sum().max(1.0);
+		if x.iter().all(|v| v.is_finite()) && residual / scale < 1e-4 {
+			return x;
+		}
+	}
+	least_squares(a, b).unwrap()
+}
+
+fn direct_solve(a: &Array2<f32>, b: &Array2<f32>) -> Result<Array2<f32>> {
+	// The kernel values grow with r^2 while the polynomial block holds 1s and raw coordinates.
+	// Factoring that in single precision loses most of the digits, so the decomposition is done in f64.
+	// The system is symmetric but indefinite (the zero block in the corner), so Cholesky is out. LU with pivoting is fine.
+	let x = linalg::lu_solve(&a.mapv(|v| v as f64), &b.mapv(|v| v as f64))?;
+	Ok(x.mapv(|v| v as f32))
+}
+
+fn least_squares(a: &Array2<f32>, b: &Array2<f32>) -> Result<Array2<f32>> {
 	// [Gilbert Strang appears as a force-ghost projection]
 	// Remember your linear algebra.
 	// Ax = b
@@ -95,11 +778,12 @@ fn least_squares(a: &Array2<f32>, b: &Array2<f32>) -> Result<Array2<f32>, ndarra
 	array([[-0.99826941,  2.20432477],
 	       [ 4.20851867,  6.90574251]])
 	*/
-	let (u, mut s, v) = a.svd(true, true)?;
-	let u = u.expect("calc_u is true but u is not present!?");
-	let v = v.expect("calc_v is true but v is not present!?");
+	let (u, mut s, v) = linalg::svd(a)?;
 	// s should have only two elements, but...
-	s.iter_mut().for_each(|value: &mut f32| { *value = if *value < 1e-6f32 { 0.0 } else { 1.0f32 / *value }; } );
+	// 'Close to zero' is relative to the largest singular value.  Backends differ in how close to zero they get
+	// on an exactly singular matrix, and in single precision that's nowhere near an absolute 1e-6.
+	let cutoff = s.iter().cloned().fold(0.0f32, f32::max) * f32::EPSILON * (a.nrows().max(a.ncols()) as f32);
+	s.iter_mut().for_each(|value: &mut f32| { *value = if *value <= cutoff.max(1e-6f32) { 0.0 } else { 1.0f32 / *value }; } );
 
 	// Ax = b -- (Ax) is in R^(p by q) so b is in R^(p by q)
 	// So A and b must have the same number of rows.  x and b must have the same number of columns.
@@ -113,15 +797,9 @@ fn least_squares(a: &Array2<f32>, b: &Array2<f32>) -> Result<Array2<f32>, ndarra
 	Ok(v_sinv_ut.dot(b))
 }
 
-fn compute_radial_distances(control: &Array2<f32>, pts: &Array2<f32>) -> Array2<f32> {
+fn compute_radial_distances(control: &Array2<f32>, pts: &Array2<f32>, kernel: &RadialBasis) -> Array2<f32> {
 	let distances = compute_pairwise_distances(pts, control);
-	distances.mapv(|value| {
-		if value > 1e-5 {
-			(value*value)*value.log10()
-		} else {
-			0.0f32
-		}
-	})
+	distances.mapv(|value| kernel.evaluate(value))
 }
 
 fn compute_pairwise_distances(a: &Array2<f32>, b: &Array2<f32>) -> Array2<f32> {
@@ -139,7 +817,7 @@ fn compute_pairwise_distances(a: &Array2<f32>, b: &Array2<f32>) -> Array2<f32> {
 
 #[cfg(test)]
 mod tests {
-	use ndarray_linalg::{assert_aclose, assert_close_l1};
+	use approx::assert_abs_diff_eq;
 	use super::*;
 	use rand::prelude::*;
 
@@ -159,15 +837,16 @@ mod tests {
 	}
 
 	#[test]
-	fn test_to_mat() {
+	fn test_points_view() {
 		let values = vec![0.0f32, 1.0, 2.0, 3.0, 4.0, 5.0];
-		let out = vec_to_mat(&values, 3, 2);
-		assert_aclose!(out[[0, 0]], 0.0, 1e-5);
-		assert_aclose!(out[[0, 1]], 1.0, 1e-5);
-		assert_aclose!(out[[1, 0]], 2.0, 1e-5);
-		assert_aclose!(out[[1, 1]], 3.0, 1e-5);
-		assert_aclose!(out[[2, 0]], 4.0, 1e-5);
-		assert_aclose!(out[[2, 1]], 5.0, 1e-5);
+		let out = points_view(&values);
+		assert_eq!(out.dim(), (3, 2));
+		assert_abs_diff_eq!(out[[0, 0]], 0.0, epsilon = 1e-5);
+		assert_abs_diff_eq!(out[[0, 1]], 1.0, epsilon = 1e-5);
+		assert_abs_diff_eq!(out[[1, 0]], 2.0, epsilon = 1e-5);
+		assert_abs_diff_eq!(out[[1, 1]], 3.0, epsilon = 1e-5);
+		assert_abs_diff_eq!(out[[2, 0]], 4.0, epsilon = 1e-5);
+		assert_abs_diff_eq!(out[[2, 1]], 5.0, epsilon = 1e-5);
 	}
 
 	#[test]
@@ -217,6 +896,374 @@ mod tests {
 			10.0, 18.0, // Exact src/dst match.
 			14.0, 14.0, // Half way on each axis plus offset.
 		];
-		assert_close_l1!(&vec_to_mat(&transformed, 6, 2), &vec_to_mat(&expected, 6, 2), 1e-3);
+		assert_abs_diff_eq!(points_view(&transformed), points_view(&expected), epsilon = 1e-3);
+	}
+
+	#[test]
+	fn test_direct_solve_matches_lstsq() {
+		let mut rng = thread_rng();
+		let a = Array2::from_shape_fn((8, 8), |(_, _)| rng.gen::<f32>()) + Array2::<f32>::eye(8) * 4.0;
+		let b = Array2::from_shape_fn((8, 2), |(_, _)| rng.gen::<f32>());
+		let direct = direct_solve(&a, &b).unwrap();
+		let svd = least_squares(&a, &b).unwrap();
+		assert!(direct.abs_diff_eq(&svd, 1e-4));
+	}
+
+	#[test]
+	fn test_duplicate_points_fall_back() {
+		// Two identical control points make the system exactly singular.  The fit should still work.
+		let src_points = vec![0.0f32, 0.0, 10.0, 0.0, 0.0, 10.0, 10.0, 10.0, 10.0, 10.0];
+		let dst_points = vec![1.0f32, 1.0, 11.0, 1.0, 1.0, 11.0, 11.0, 11.0, 11.0, 11.0];
+		let tps = ThinPlateSpline::new(&src_points, &dst_points, 0.0);
+		let transformed = tps.transform(&vec![5.0, 5.0]);
+		assert_abs_diff_eq!(transformed[0], 6.0, epsilon = 1e-3);
+		assert_abs_diff_eq!(transformed[1], 6.0, epsilon = 1e-3);
+	}
+
+	/// Not a real benchmark harness, but enough to compare the two solvers.
+	/// Run with `cargo test --release -- --ignored --nocapture bench_solvers`.
+	#[test]
+	#[ignore]
+	fn bench_solvers() {
+		let mut rng = thread_rng();
+		for n_c in [50usize, 200, 500] {
+			let src_points: Vec<f32> = (0..2*n_c).map(|_| rng.gen_range(0.0f32..1024.0)).collect();
+			let dst_points: Vec<f32> = src_points.iter().map(|v| v + rng.gen_range(-10.0f32..10.0)).collect();
+			let source_mat = points_view(&src_points).to_owned();
+			let dest_mat = points_view(&dst_points).to_owned();
+			let k = compute_radial_distances(&source_mat, &source_mat, &RadialBasis::ThinPlate);
+			let x_p = concatenate(Axis(1), &[(&Array2::ones((n_c, 1))).into(), (&source_mat).into()]).unwrap();
+			let a_top = concatenate(Axis(1), &[(&k).into(), (&x_p).into()]).unwrap();
+			let a_bottom = concatenate(Axis(1), &[(&x_p.t()).into(), (&Array2::zeros((3, 3))).into()]).unwrap();
+			let a = concatenate(Axis(0), &[(&a_top).into(), (&a_bottom).into()]).unwrap();
+			let y = concatenate(Axis(0), &[(&dest_mat).into(), (&Array2::zeros((3, 2))).into()]).unwrap();
+
+			let start = std::time::Instant::now();
+			direct_solve(&a, &y).unwrap();
+			let direct_time = start.elapsed();
+			let start = std::time::Instant::now();
+			least_squares(&a, &y).unwrap();
+			let svd_time = start.elapsed();
+			println!("n_c = {n_c}: direct {direct_time:?}, svd {svd_time:?}");
+		}
+	}
+
+	fn fit_pair() -> (Vec<f32>, Vec<f32>) {
+		let src_points = vec![
+			0.0f32, 0.0,
+			100.0, 0.0,
+			0.0, 100.0,
+			100.0, 100.0,
+			50.0, 50.0,
+			20.0, 70.0,
+		];
+		let dst_points = vec![
+			0.0f32, 0.0,
+			100.0, 10.0,
+			-10.0, 100.0,
+			100.0, 100.0,
+			60.0, 40.0,
+			25.0, 80.0,
+		];
+		(src_points, dst_points)
+	}
+
+	fn assert_same_warp(a: &ThinPlateSpline, b: &ThinPlateSpline) {
+		let probe = vec![10.0f32, 10.0, 50.0, 50.0, 75.0, 30.0, 90.0, 95.0, -20.0, 130.0];
+		let (a_out, b_out) = (a.transform(&probe), b.transform(&probe));
+		assert_abs_diff_eq!(points_view(&a_out), points_view(&b_out), epsilon = 1e-2);
+	}
+
+	#[test]
+	fn test_incremental_move() {
+		let (mut src_points, mut dst_points) = fit_pair();
+		let mut tps = ThinPlateSpline::new(&src_points, &dst_points, 0.0);
+
+		// Destination only.
+		tps.move_control_point(4, None, Some(&[55.0, 45.0]));
+		dst_points[8] = 55.0;
+		dst_points[9] = 45.0;
+		assert_same_warp(&tps, &ThinPlateSpline::new(&src_points, &dst_points, 0.0));
+
+		// Source and destination, a few times over to make sure the updates don't drift.
+		for step in 0..5 {
+			let (x, y) = (30.0 + step as f32 * 3.0, 60.0 - step as f32 * 2.0);
+			tps.move_control_point(5, Some(&[x, y]), Some(&[x + 4.0, y + 9.0]));
+			src_points[10] = x;
+			src_points[11] = y;
+			dst_points[10] = x + 4.0;
+			dst_points[11] = y + 9.0;
+		}
+		assert_same_warp(&tps, &ThinPlateSpline::new(&src_points, &dst_points, 0.0));
+	}
+
+	#[test]
+	fn test_incremental_add_remove() {
+		let (mut src_points, mut dst_points) = fit_pair();
+		let mut tps = ThinPlateSpline::new(&src_points, &dst_points, 0.0);
+
+		tps.add_control_point(&[80.0, 20.0], &[85.0, 15.0]);
+		src_points.extend([80.0, 20.0]);
+		dst_points.extend([85.0, 15.0]);
+		assert_eq!(tps.get_num_control_points(), 7);
+		assert_same_warp(&tps, &ThinPlateSpline::new(&src_points, &dst_points, 0.0));
+
+		tps.remove_control_point(1);
+		src_points.drain(2..4);
+		dst_points.drain(2..4);
+		assert_eq!(tps.get_num_control_points(), 6);
+		assert_same_warp(&tps, &ThinPlateSpline::new(&src_points, &dst_points, 0.0));
+	}
+
+	#[test]
+	#[should_panic(expected = "Control point 6 doesn't exist")]
+	fn test_incremental_bad_index() {
+		let (src_points, dst_points) = fit_pair();
+		let mut tps = ThinPlateSpline::new(&src_points, &dst_points, 0.0);
+		tps.move_control_point(6, None, Some(&[1.0, 1.0]));
+	}
+
+	#[test]
+	fn test_inverse_round_trip() {
+		let (src_points, dst_points) = fit_pair();
+		let tps = ThinPlateSpline::new(&src_points, &dst_points, 0.0);
+		// At the control points the inverse should give back the sources exactly.
+		let back = tps.transform_inverse(&dst_points);
+		assert_abs_diff_eq!(points_view(&back), points_view(&src_points), epsilon = 1e-2);
+
+		// In between the swapped fit isn't the true inverse, but the Newton refinement should be.
+		let grid: Vec<f32> = (0..10).flat_map(|i| (0..10).flat_map(move |j| [i as f32 * 10.0 + 3.0, j as f32 * 10.0 + 7.0])).collect();
+		assert!(tps.round_trip_error(&grid) < 1e-2);
+		let forward = tps.transform(&grid);
+		let swapped_error = tps.inverse().transform(&forward).iter().zip(grid.iter()).map(|(a, b)| (a - b).abs()).fold(0.0f32, f32::max);
+		let newton_error = tps.transform_inverse(&forward).iter().zip(grid.iter()).map(|(a, b)| (a - b).abs()).fold(0.0f32, f32::max);
+		assert!(newton_error <= swapped_error);
+
+		// The inverse fit is made once and kept, until an edit makes it stale.
+		let mut tps = tps;
+		assert!(tps.inverse_fit.get().is_some());
+		tps.move_control_point(4, None, Some(&[55.0, 45.0]));
+		assert!(tps.inverse_fit.get().is_none());
+		let mut moved = dst_points.clone();
+		moved[8..10].copy_from_slice(&[55.0, 45.0]);
+		let back = tps.transform_inverse(&moved);
+		assert_abs_diff_eq!(points_view(&back), points_view(&src_points), epsilon = 1e-2);
+	}
+
+	#[test]
+	fn test_jacobian_matches_finite_difference() {
+		let (src_points, dst_points) = fit_pair();
+		let probe = vec![10.0f32, 10.0, 50.0, 50.0, 75.0, 30.0, 20.0, 70.0];
+		let kernels = [
+			RadialBasis::ThinPlate,
+			RadialBasis::Gaussian { epsilon: 0.02 },
+			RadialBasis::Multiquadric { epsilon: 0.02 },
+			RadialBasis::InverseMultiquadric { epsilon: 0.02 },
+			RadialBasis::Wendland { radius: 120.0 },
+		];
+		for kernel in kernels {
+			let tps = ThinPlateSpline::new_with_kernel(&src_points, &dst_points, 0.1, kernel);
+			let jacobian = tps.jacobian(&probe);
+			let h = 1e-2f32;
+			for (i, pt) in probe.chunks_exact(2).enumerate() {
+				let f = tps.transform(&vec![pt[0] - h, pt[1], pt[0] + h, pt[1], pt[0], pt[1] - h, pt[0], pt[1] + h]);
+				let numeric = [(f[2] - f[0]) / (2.0*h), (f[6] - f[4]) / (2.0*h), (f[3] - f[1]) / (2.0*h), (f[7] - f[5]) / (2.0*h)];
+				for k in 0..4 {
+					assert_abs_diff_eq!(jacobian[4*i + k], numeric[k], epsilon = 2e-2);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn test_fold_detected() {
+		// Swap the destinations of two neighboring points so the warp has to fold to hit both.
+		let src_points = vec![0.0f32, 0.0, 100.0, 0.0, 0.0, 100.0, 100.0, 100.0, 40.0, 50.0, 60.0, 50.0];
+		let dst_points = vec![0.0f32, 0.0, 100.0, 0.0, 0.0, 100.0, 100.0, 100.0, 60.0, 50.0, 40.0, 50.0];
+		let tps = ThinPlateSpline::new(&src_points, &dst_points, 0.0);
+		assert!(tps.jacobian_determinant(&vec![50.0, 50.0])[0] <= 0.0);
+		// Far from the swapped pair, the warp is still well behaved.
+		assert!(tps.jacobian_determinant(&vec![5.0, 95.0])[0] > 0.0);
+	}
+
+	#[test]
+	fn test_alpha_scale_invariant() {
+		// The same layout at 1x and 16x should smooth identically, up to the scale.
+		let (src_points, dst_points) = fit_pair();
+		let big_src: Vec<f32> = src_points.iter().map(|v| v * 16.0).collect();
+		let big_dst: Vec<f32> = dst_points.iter().map(|v| v * 16.0).collect();
+		let small = ThinPlateSpline::new(&src_points, &dst_points, 0.5);
+		let big = ThinPlateSpline::new(&big_src, &big_dst, 0.5);
+		let probe = vec![10.0f32, 10.0, 50.0, 50.0, 75.0, 30.0];
+		let big_probe: Vec<f32> = probe.iter().map(|v| v * 16.0).collect();
+		let small_out: Vec<f32> = small.transform(&probe).iter().map(|v| v * 16.0).collect();
+		let big_out = big.transform(&big_probe);
+		assert_abs_diff_eq!(points_view(&small_out), points_view(&big_out), epsilon = 1e-1);
+		// And it should actually be smoothing, not interpolating.
+		let at_controls = small.transform(&src_points);
+		assert!(at_controls.iter().zip(dst_points.iter()).any(|(a, b)| (a - b).abs() > 0.1));
+	}
+
+	#[test]
+	fn test_automatic_alpha() {
+		let mut rng = StdRng::seed_from_u64(42);
+		let mut src_points = vec![];
+		let mut clean_points = vec![];
+		let mut noisy_points = vec![];
+		for i in 0..8 {
+			for j in 0..8 {
+				let (x, y) = (i as f32 * 40.0 + rng.gen_range(-5.0f32..5.0), j as f32 * 40.0 + rng.gen_range(-5.0f32..5.0));
+				// A smooth bulge.
+				let (tx, ty) = (x + 10.0 * (y / 100.0).sin(), y + 10.0 * (x / 100.0).cos());
+				src_points.extend([x, y]);
+				clean_points.extend([tx, ty]);
+				noisy_points.extend([tx + rng.gen_range(-4.0f32..4.0), ty + rng.gen_range(-4.0f32..4.0)]);
+			}
+		}
+		let clean_alpha = ThinPlateSpline::select_alpha(points_view(&src_points), points_view(&clean_points), RadialBasis::ThinPlate, None);
+		let noisy_alpha = ThinPlateSpline::select_alpha(points_view(&src_points), points_view(&noisy_points), RadialBasis::ThinPlate, None);
+		assert!(noisy_alpha > clean_alpha);
+
+		// The smoothed fit of the noisy points should land closer to the clean function than interpolating the noise.
+		let smoothed = ThinPlateSpline::new_with_automatic_alpha(&src_points, &noisy_points, RadialBasis::ThinPlate);
+		let interpolated = ThinPlateSpline::new(&src_points, &noisy_points, 0.0);
+		let error = |tps: &ThinPlateSpline| tps.transform(&src_points).iter().zip(clean_points.iter()).map(|(a, b)| (a - b)*(a - b)).sum::<f32>();
+		assert!(error(&smoothed) < error(&interpolated));
+	}
+
+	#[test]
+	fn test_three_dimensions() {
+		// The corners of a cube plus a few interior points, pushed through a twist.
+		let mut source = vec![];
+		for i in 0..3 {
+			for j in 0..3 {
+				for k in 0..3 {
+					source.extend([i as f32 * 50.0, j as f32 * 50.0, k as f32 * 50.0]);
+				}
+			}
+		}
+		let source = Array2::from_shape_vec((27, 3), source).unwrap();
+		let target = Array2::from_shape_fn((27, 3), |(i, d)| {
+			let (x, y, z) = (source[(i, 0)], source[(i, 1)], source[(i, 2)]);
+			[x + 0.1 * z, y - 0.05 * z, z + 10.0 * (x / 100.0).sin()][d]
+		});
+		let tps = ThinPlateSpline::fit(source.view(), target.view(), Some(0.0), RadialBasis::ThinPlate, None);
+		assert_eq!(tps.get_source_dimensions(), 3);
+		assert_eq!(tps.get_target_dimensions(), 3);
+		assert_abs_diff_eq!(tps.apply(source.view()), target, epsilon = 1e-2);
+		assert_abs_diff_eq!(tps.apply_inverse(target.view()), source, epsilon = 1e-2);
+
+		// (x, y, t) -> (x, y) is fine too.
+		let flat = target.slice(s![.., ..2]);
+		let tps = ThinPlateSpline::fit(source.view(), flat, Some(0.0), RadialBasis::ThinPlate, None);
+		assert_abs_diff_eq!(tps.apply(source.view()), flat, epsilon = 1e-2);
+		assert_eq!(tps.jacobian_matrices(source.view()).dim(), (27, 2, 3));
+	}
+
+	#[test]
+	#[should_panic(expected = "only defined for 2D splines")]
+	fn test_flat_jacobian_needs_2d() {
+		let source = Array2::from_shape_fn((8, 3), |(i, d)| ((i >> d) & 1) as f32 * 10.0);
+		let tps = ThinPlateSpline::fit(source.view(), source.view(), Some(0.0), RadialBasis::ThinPlate, None);
+		tps.jacobian_determinant(&vec![5.0, 5.0, 5.0]);
+	}
+
+	#[test]
+	fn test_weights() {
+		let (src_points, dst_points) = fit_pair();
+		let source = points_view(&src_points);
+		let target = points_view(&dst_points);
+		let uniform = ThinPlateSpline::fit(source, target, Some(1.0), RadialBasis::ThinPlate, None);
+		let weights = array![1.0f32, 1.0, 1.0, 1.0, 1000.0, 1.0];
+		let weighted = ThinPlateSpline::fit(source, target, Some(1.0), RadialBasis::ThinPlate, Some(weights.view()));
+		// The heavily weighted point is followed much more closely than with uniform weights.
+		let miss = |tps: &ThinPlateSpline| {
+			let out = tps.transform(&vec![src_points[8], src_points[9]]);
+			((out[0] - dst_points[8]).powi(2) + (out[1] - dst_points[9]).powi(2)).sqrt()
+		};
+		assert!(miss(&weighted) < 0.1 * miss(&uniform));
+		assert!(miss(&weighted) < 1e-1);
+
+		// Uniform weights are the same as none, at any scale.
+		let doubled = Array1::from_elem(6, 2.0f32);
+		let same = ThinPlateSpline::fit(source, target, Some(2.0), RadialBasis::ThinPlate, Some(doubled.view()));
+		assert_same_warp(&uniform, &same);
+	}
+
+	#[test]
+	fn test_serialize_round_trip() {
+		let (src_points, dst_points) = fit_pair();
+		let tps = ThinPlateSpline::new_with_kernel(&src_points, &dst_points, 0.1, RadialBasis::Gaussian { epsilon: 0.02 });
+
+		let from_json = ThinPlateSpline::from_json(&tps.to_json().unwrap()).unwrap();
+		assert_same_warp(&tps, &from_json);
+		let from_bytes = ThinPlateSpline::from_bytes(&tps.to_bytes().unwrap()).unwrap();
+		assert_same_warp(&tps, &from_bytes);
+		assert_eq!(from_bytes.get_alpha(), tps.get_alpha());
+
+		// A loaded spline can still be edited incrementally.
+		let mut loaded = from_bytes;
+		loaded.move_control_point(4, None, Some(&[55.0, 45.0]));
+		let mut edited = tps.clone();
+		edited.move_control_point(4, None, Some(&[55.0, 45.0]));
+		assert_same_warp(&loaded, &edited);
+	}
+
+	#[test]
+	fn test_deserialize_rejects_bad_shapes() {
+		let (src_points, dst_points) = fit_pair();
+		let mut data = SerializedSpline::from(ThinPlateSpline::new(&src_points, &dst_points, 0.0));
+		data.parameters.pop();
+		assert!(ThinPlateSpline::try_from(data.clone()).is_err());
+		data.version = SERIALIZED_VERSION + 1;
+		assert!(ThinPlateSpline::from_json(&serde_json::to_string(&data).unwrap()).is_err());
+	}
+
+	#[test]
+	fn test_kernel_values() {
+		assert_abs_diff_eq!(RadialBasis::ThinPlate.evaluate(0.0), 0.0, epsilon = 1e-6);
+		assert_abs_diff_eq!(RadialBasis::ThinPlate.evaluate(1.0), 0.0, epsilon = 1e-6);
+		assert_abs_diff_eq!(RadialBasis::ThinPlate.evaluate(std::f32::consts::E), std::f32::consts::E.powi(2), epsilon = 1e-4);
+		assert_abs_diff_eq!(RadialBasis::Gaussian { epsilon: 0.5 }.evaluate(2.0), (-1.0f32).exp(), epsilon = 1e-6);
+		assert_abs_diff_eq!(RadialBasis::Multiquadric { epsilon: 1.0 }.evaluate(0.0), 1.0, epsilon = 1e-6);
+		assert_abs_diff_eq!(RadialBasis::InverseMultiquadric { epsilon: 0.75 }.evaluate(4.0), 1.0 / 10.0f32.sqrt(), epsilon = 1e-6);
+		assert_abs_diff_eq!(RadialBasis::Wendland { radius: 10.0 }.evaluate(0.0), 1.0, epsilon = 1e-6);
+		assert_abs_diff_eq!(RadialBasis::Wendland { radius: 10.0 }.evaluate(10.0), 0.0, epsilon = 1e-6);
+		assert_abs_diff_eq!(RadialBasis::Wendland { radius: 10.0 }.evaluate(25.0), 0.0, epsilon = 1e-6);
+	}
+
+	#[test]
+	fn test_kernels_interpolate_control_points() {
+		// A non-affine mapping, so the radial part actually has to do some work.
+		let src_points = vec![
+			0.0f32, 0.0,
+			10.0, 0.0,
+			0.0, 10.0,
+			10.0, 10.0,
+			5.0, 5.0,
+			2.0, 7.0,
+		];
+		let dst_points = vec![
+			0.0f32, 0.0,
+			10.0, 1.0,
+			-1.0, 10.0,
+			10.0, 10.0,
+			6.0, 4.0,
+			2.5, 8.0,
+		];
+		let kernels = [
+			RadialBasis::ThinPlate,
+			RadialBasis::Gaussian { epsilon: 0.2 },
+			RadialBasis::Multiquadric { epsilon: 0.2 },
+			RadialBasis::InverseMultiquadric { epsilon: 0.2 },
+			RadialBasis::Wendland { radius: 12.0 },
+		];
+		for kernel in kernels {
+			let tps = ThinPlateSpline::new_with_kernel(&src_points, &dst_points, 0.0, kernel);
+			let transformed = tps.transform(&src_points);
+			for (a, b) in transformed.iter().zip(dst_points.iter()) {
+				assert!((a - b).abs() < 1e-2, "{kernel:?}: {transformed:?} != {dst_points:?}");
+			}
+		}
 	}
 }
\ No newline at end of file
@@ -0,0 +1,380 @@
+// Writing a morph straight to a video file.
+// Frames go to a local ffmpeg as raw RGB over a pipe, so nothing touches the disk but the finished video.
+// Without ffmpeg the fallback is a YUV4MPEG2 (.y4m) file, uncompressed but written in pure Rust, which most players
+// and every encoder can read.
+
+use anyhow::{anyhow, bail, Context, Result};
+use image::{DynamicImage, RgbImage};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread::JoinHandle;
+
+use crate::export::{blend_amount, render_animation_frame, ExportInput};
+
+/// Which writer to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum VideoBackend {
+	/// ffmpeg if it can be run, otherwise Y4M next to the requested path.
+	Auto,
+	Ffmpeg,
+	/// Always Y4M, written with a .y4m extension whatever the requested path has.
+	Y4m,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct VideoSettings {
+	pub backend: VideoBackend,
+	/// The ffmpeg executable, either a path or a name to look up on PATH.
+	pub ffmpeg: PathBuf,
+	/// An ffmpeg encoder name, e.g. "libx264", "libx265", "libvpx-vp9" or "prores_ks".
+	pub codec: String,
+	/// Constant rate factor.  Lower is better and bigger, and the useful range depends on the codec.
+	/// None leaves it to the codec's default, for codecs without CRF.
+	pub crf: Option<u8>,
+	/// The ffmpeg pixel format of the output, e.g. "yuv420p" for the widest player support.
+	pub pixel_format: String,
+	pub fps: f32,
+}
+
+impl Default for VideoSettings {
+	fn default() -> Self {
+		VideoSettings {
+			backend: VideoBackend::Auto,
+			ffmpeg: PathBuf::from("ffmpeg"),
+			codec: "libx264".to_string(),
+			crf: Some(18),
+			pixel_format: "yuv420p".to_string(),
+			fps: 24.0,
+		}
+	}
+}
+
+/// Somewhere to write video frames to, one at a time.  Every frame has to be the same size as the first.
+pub trait VideoSink {
+	fn write_frame(&mut self, frame: &RgbImage) -> Result<()>;
+	/// Flush everything and close the file.  Frames written after this are an error.
+	fn finish(&mut self) -> Result<()>;
+}
+
+/// A frame rate as the exact fraction video containers want.  The NTSC rates come out as their usual x/1001.
+pub fn frame_rate_ratio(fps: f32) -> (u32, u32) {
+	for base in [24, 30, 60, 120] {
+		if (fps - base as f32 * 1000.0 / 1001.0).abs() < 0.005 {
+			return (base * 1000, 1001);
+		}
+	}
+	let numerator = (fps * 1000.0).round().max(1.0) as u32;
+	let divisor = gcd(numerator, 1000);
+	(numerator / divisor, 1000 / divisor)
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+	if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Whether a pixel format halves the chroma resolution horizontally and vertically.  Those need even sizes, and
+/// ffmpeg only finds that out after it's been sent the first frame.
+fn chroma_subsampling(pixel_format: &str) -> (bool, bool) {
+	const BOTH: [&str; 6] = ["yuv420", "yuvj420", "yuva420", "nv12", "nv21", "p010"];
+	const HORIZONTAL: [&str; 4] = ["yuv422", "yuvj422", "yuva422", "nv16"];
+	if BOTH.iter().any(|f| pixel_format.starts_with(f)) {
+		(true, true)
+	} else if HORIZONTAL.iter().any(|f| pixel_format.starts_with(f)) {
+		(true, false)
+	} else {
+		(false, false)
+	}
+}
+
+/// Whether the ffmpeg executable can be run at all.
+pub fn ffmpeg_available(ffmpeg: &Path) -> bool {
+	Command::new(ffmpeg).arg("-version").stdout(Stdio::null()).stderr(Stdio::null()).status().map(|s| s.success()).unwrap_or(false)
+}
+
+/// Streams frames into an ffmpeg subprocess.
+pub struct FfmpegSink {
+	child: Child,
+	stdin: Option<ChildStdin>,
+	/// ffmpeg's error output, collected on a thread so a chatty encoder can't fill the pipe and stall.
+	stderr: Option<JoinHandle<String>>,
+	width: u32,
+	height: u32,
+}
+
+impl FfmpegSink {
+	pub fn new(path: &Path, width: u32, height: u32, settings: &VideoSettings) -> Result<Self> {
+		let (horizontal, vertical) = chroma_subsampling(&settings.pixel_format);
+		if (horizontal && width % 2 == 1) || (vertical && height % 2 == 1) {
+			bail!("{} video needs an even size, not {}x{}.  Crop or resize the frames, or pick a pixel format like yuv444p.", settings.pixel_format, width, height);
+		}
+		let (numerator, denominator) = frame_rate_ratio(settings.fps);
+		let mut command = Command::new(&settings.ffmpeg);
+		command.args(["-hide_banner", "-loglevel", "error", "-y"])
+			.args(["-f", "rawvideo", "-pix_fmt", "rgb24"])
+			.args(["-s", &format!("{}x{}", width, height)])
+			.args(["-r", &format!("{}/{}", numerator, denominator)])
+			.args(["-i", "-"])
+			.args(["-c:v", &settings.codec]);
+		if let Some(crf) = settings.crf {
+			command.args(["-crf", &crf.to_string()]);
+		}
+		command.args(["-pix_fmt", &settings.pixel_format]).arg(path);
+		let mut child = command.stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped()).spawn()
+			.with_context(|| format!("Couldn't run {}.", settings.ffmpeg.display()))?;
+		let stdin = child.stdin.take();
+		let mut stderr = child.stderr.take().ok_or_else(|| anyhow!("ffmpeg's error output wasn't captured."))?;
+		let stderr = std::thread::spawn(move || {
+			let mut output = String::new();
+			let _ = stderr.read_to_string(&mut output);
+			output
+		});
+		Ok(FfmpegSink { child, stdin, stderr: Some(stderr), width, height })
+	}
+
+	fn error_output(&mut self) -> String {
+		self.stderr.take().and_then(|thread| thread.join().ok()).unwrap_or_default().trim().to_string()
+	}
+}
+
+impl VideoSink for FfmpegSink {
+	fn write_frame(&mut self, frame: &RgbImage) -> Result<()> {
+		if frame.dimensions() != (self.width, self.height) {
+			bail!("Every frame of a video has to be the same size.");
+		}
+		let stdin = self.stdin.as_mut().ok_or_else(|| anyhow!("The video is already finished."))?;
+		if let Err(error) = stdin.write_all(frame.as_raw()) {
+			// ffmpeg quit early, and what it said is more useful than the broken pipe.
+			self.stdin = None;
+			let _ = self.child.wait();
+			bail!("ffmpeg stopped accepting frames ({}): {}", error, self.error_output());
+		}
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Result<()> {
+		// Closing the pipe tells ffmpeg that was the last frame.
+		drop(self.stdin.take());
+		let status = self.child.wait()?;
+		let output = self.error_output();
+		if !status.success() {
+			bail!("ffmpeg failed with {}: {}", status, output);
+		}
+		Ok(())
+	}
+}
+
+impl Drop for FfmpegSink {
+	fn drop(&mut self) {
+		if self.stdin.take().is_some() {
+			let _ = self.child.kill();
+			let _ = self.child.wait();
+		}
+	}
+}
+
+/// Writes uncompressed YUV4MPEG2, 8 bit 4:2:0 with BT.601 limited range, which is what players expect from it.
+pub struct Y4mSink<W: Write> {
+	writer: Option<W>,
+	/// The writer is moved here by `finish`, so nothing more can be written to it.
+	finished: Option<W>,
+	width: u32,
+	height: u32,
+}
+
+impl Y4mSink<BufWriter<File>> {
+	pub fn create(path: &Path, width: u32, height: u32, fps: f32) -> Result<Self> {
+		Y4mSink::new(BufWriter::new(File::create(path)?), width, height, fps)
+	}
+}
+
+impl<W: Write> Y4mSink<W> {
+	pub fn new(mut writer: W, width: u32, height: u32, fps: f32) -> Result<Self> {
+		let (numerator, denominator) = frame_rate_ratio(fps);
+		writeln!(writer, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg", width, height, numerator, denominator)?;
+		Ok(Y4mSink { writer: Some(writer), finished: None, width, height })
+	}
+
+	/// The writer back, once finished.
+	pub fn into_inner(self) -> Option<W> {
+		self.finished
+	}
+}
+
+impl<W: Write> VideoSink for Y4mSink<W> {
+	fn write_frame(&mut self, frame: &RgbImage) -> Result<()> {
+		if frame.dimensions() != (self.width, self.height) {
+			bail!("Every frame of a video has to be the same size.");
+		}
+		let writer = self.writer.as_mut().ok_or_else(|| anyhow!("The video is already finished."))?;
+		let (luma, blue, red) = rgb_to_yuv420(frame);
+		writer.write_all(b"FRAME\n")?;
+		writer.write_all(&luma)?;
+		writer.write_all(&blue)?;
+		writer.write_all(&red)?;
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Result<()> {
+		let mut writer = self.writer.take().ok_or_else(|| anyhow!("The video is already finished."))?;
+		writer.flush()?;
+		self.finished = Some(writer);
+		Ok(())
+	}
+}
+
+/// Split a frame into Y, Cb and Cr planes, with chroma averaged over each 2x2 block.
+fn rgb_to_yuv420(frame: &RgbImage) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+	let (width, height) = frame.dimensions();
+	let luma = frame.pixels().map(|p| {
+		let [r, g, b] = p.0.map(|c| c as f32);
+		(16.0 + (65.481*r + 128.553*g + 24.966*b) / 255.0).round() as u8
+	}).collect();
+	let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+	let mut blue = Vec::with_capacity((chroma_width * chroma_height) as usize);
+	let mut red = Vec::with_capacity((chroma_width * chroma_height) as usize);
+	for cy in 0..chroma_height {
+		for cx in 0..chroma_width {
+			let mut sum = [0.0f32; 3];
+			let mut count = 0.0;
+			for y in (2 * cy)..(2 * cy + 2).min(height) {
+				for x in (2 * cx)..(2 * cx + 2).min(width) {
+					let p = frame.get_pixel(x, y);
+					for k in 0..3 {
+						sum[k] += p[k] as f32;
+					}
+					count += 1.0;
+				}
+			}
+			let [r, g, b] = sum.map(|s| s / count);
+			blue.push((128.0 + (-37.797*r - 74.203*g + 112.0*b) / 255.0).round() as u8);
+			red.push((128.0 + (112.0*r - 93.786*g - 18.214*b) / 255.0).round() as u8);
+		}
+	}
+	(luma, blue, red)
+}
+
+/// Render every frame in the range into a video.  Alpha is dropped, since few codecs carry it.
+/// Returns the path actually written, which for Y4M, asked for or as the fallback, is `path` with a .y4m extension.
+pub fn export_video(input: &mut ExportInput, frames: RangeInclusive<u32>, settings: &VideoSettings, path: &Path) -> Result<PathBuf> {
+	if settings.fps.is_nan() || settings.fps <= 0.0 {
+		bail!("Frame rate must be positive, not {}.", settings.fps);
+	}
+	if frames.is_empty() {
+		bail!("There are no frames to export.");
+	}
+	let use_ffmpeg = match settings.backend {
+		VideoBackend::Auto => ffmpeg_available(&settings.ffmpeg),
+		VideoBackend::Ffmpeg => true,
+		VideoBackend::Y4m => false,
+	};
+	// Y4M data in a file named .mp4 or the like would only confuse whatever opens it.
+	let path = if use_ffmpeg { path.to_path_buf() } else { path.with_extension("y4m") };
+	if !use_ffmpeg && settings.backend == VideoBackend::Auto {
+		log::warn!("Couldn't run {}, writing {} instead.", settings.ffmpeg.display(), path.display());
+	}
+
+	// The sink is opened once the first frame says how big the video is.
+	let mut sink: Option<Box<dyn VideoSink>> = None;
+	for frame in frames.clone() {
+		let image = render_animation_frame(input, frame, blend_amount(frame, &frames))?;
+		let image = DynamicImage::ImageRgba32F(image).to_rgb8();
+		if sink.is_none() {
+			let (width, height) = image.dimensions();
+			sink = Some(if use_ffmpeg {
+				Box::new(FfmpegSink::new(&path, width, height, settings)?)
+			} else {
+				Box::new(Y4mSink::create(&path, width, height, settings.fps)?)
+			});
+		}
+		sink.as_mut().unwrap().write_frame(&image)?;
+	}
+	sink.as_mut().unwrap().finish()?;
+	Ok(path)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::export::tests::{black_to_white, scratch_directory};
+	use crate::morph::{Dissolve, MorphSettings};
+	use image::Rgb;
+
+	#[test]
+	fn test_frame_rate_ratio() {
+		assert_eq!(frame_rate_ratio(24.0), (24, 1));
+		assert_eq!(frame_rate_ratio(12.5), (25, 2));
+		assert_eq!(frame_rate_ratio(29.97), (30000, 1001));
+		assert_eq!(frame_rate_ratio(23.976), (24000, 1001));
+	}
+
+	#[test]
+	fn test_odd_size() {
+		// Caught before ffmpeg is even run.
+		let settings = VideoSettings { ffmpeg: PathBuf::from("no_ffmpeg_here"), ..Default::default() };
+		let error = FfmpegSink::new(Path::new("morph.mp4"), 15, 10, &settings).err().unwrap();
+		assert!(error.to_string().contains("even size"), "{}", error);
+		let settings = VideoSettings { pixel_format: "yuv422p".to_string(), ..settings };
+		assert!(FfmpegSink::new(Path::new("morph.mp4"), 16, 9, &settings).err().unwrap().to_string().contains("Couldn't run"));
+		assert!(FfmpegSink::new(Path::new("morph.mp4"), 15, 10, &settings).err().unwrap().to_string().contains("even size"));
+		assert_eq!(chroma_subsampling("yuv444p"), (false, false));
+	}
+
+	#[test]
+	fn test_y4m() {
+		// Odd sizes round the chroma planes up.
+		let frame = RgbImage::from_fn(5, 3, |x, _| if x < 2 { Rgb([255, 255, 255]) } else { Rgb([255, 0, 0]) });
+		let mut sink = Y4mSink::new(vec![], 5, 3, 25.0).unwrap();
+		sink.write_frame(&frame).unwrap();
+		sink.write_frame(&frame).unwrap();
+		assert!(sink.write_frame(&RgbImage::new(4, 3)).is_err());
+		sink.finish().unwrap();
+		assert!(sink.write_frame(&frame).is_err());
+		assert!(sink.finish().is_err());
+		let data = sink.into_inner().unwrap();
+
+		let header = b"YUV4MPEG2 W5 H3 F25:1 Ip A1:1 C420jpeg\n";
+		assert!(data.starts_with(header));
+		let frame_size = 6 + 15 + 2 * 3 * 2;
+		assert_eq!(data.len(), header.len() + 2 * frame_size);
+		let planes = &data[header.len() + 6..header.len() + frame_size];
+		// White is Y 235 with neutral chroma, and red is Y 81, Cb 90, Cr 240.
+		assert_eq!(&planes[0..5], &[235, 235, 81, 81, 81]);
+		assert_eq!((planes[15], planes[21]), (128, 128));
+		assert_eq!((planes[17], planes[23]), (90, 240));
+	}
+
+	#[test]
+	fn test_export_video() {
+		let directory = scratch_directory("video");
+		std::fs::create_dir_all(&directory).unwrap();
+		let (animation, mut left, mut right) = black_to_white();
+		let mut input = ExportInput { animation: &animation, left: &mut left, right: &mut right, morph_settings: &MorphSettings::default(), dissolve: &Dissolve::Uniform };
+		let missing = VideoSettings { ffmpeg: directory.join("no_ffmpeg_here"), ..Default::default() };
+
+		// Asking for ffmpeg specifically fails without it, but Auto falls back.
+		let ffmpeg_only = VideoSettings { backend: VideoBackend::Ffmpeg, ..missing.clone() };
+		let path = directory.join("morph.mp4");
+		assert!(export_video(&mut input, 0..=4, &ffmpeg_only, &path).is_err());
+		let written = export_video(&mut input, 0..=4, &missing, &path).unwrap();
+		assert_eq!(written, directory.join("morph.y4m"));
+
+		let data = std::fs::read(&written).unwrap();
+		let header = b"YUV4MPEG2 W16 H12 F24:1 Ip A1:1 C420jpeg\n";
+		let frame_size = 6 + 16 * 12 + 2 * 8 * 6;
+		assert_eq!(data.len(), header.len() + 5 * frame_size);
+		// Black to white in luma.
+		assert_eq!(data[header.len() + 6], 16);
+		assert_eq!(data[header.len() + 4 * frame_size + 6], 235);
+
+		// Asking for Y4M gets the right extension too.
+		let y4m_only = VideoSettings { backend: VideoBackend::Y4m, ..missing.clone() };
+		let written = export_video(&mut input, 0..=0, &y4m_only, &directory.join("only.mp4")).unwrap();
+		assert_eq!(written, directory.join("only.y4m"));
+		assert!(!directory.join("only.mp4").exists());
+		std::fs::remove_dir_all(&directory).unwrap();
+	}
+}
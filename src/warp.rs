@@ -0,0 +1,251 @@
+use crate::compact_spline::CompactSpline;
+use crate::moving_least_squares::{MlsMode, MovingLeastSquares};
+use crate::thin_plate_spline::{RadialBasis, ThinPlateSpline};
+
+/// A deformation fit from a set of point correspondences.
+/// All points are in the form [x, y, x, y, ...], same as `Animation::get_points`.
+pub trait Warp {
+	/// Map the given points from the source space into the destination space.
+	fn transform(&self, points: &Vec<f32>) -> Vec<f32>;
+
+	/// Map the given points from the destination space back into the source space.
+	/// Returns None if the warp doesn't support inversion.
+	fn transform_inverse(&self, _points: &Vec<f32>) -> Option<Vec<f32>> {
+		None
+	}
+
+	/// The Jacobian of the forward transform at each of the given points.
+	/// Each point produces four values, [dx'/dx, dx'/dy, dy'/dx, dy'/dy].
+	/// Returns None if the warp doesn't support it.
+	fn jacobian(&self, _points: &Vec<f32>) -> Option<Vec<f32>> {
+		None
+	}
+}
+
+/// A light smoothing which still passes very close to the control points.
+pub const DEFAULT_ALPHA: f32 = 1e-3;
+
+/// Which deformation to use and its parameters.  This is the configurable piece: hold one of these and
+/// call `fit` whenever the correspondences change.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum WarpMethod {
+	/// Alpha is on the normalized coordinates, so the same value smooths the same on any image size.
+	/// If alpha is None, it's picked by generalized cross-validation, which costs about ten fits every time this is fit,
+	/// so it's better to pick it once with `ThinPlateSpline::select_alpha` when the correspondences change.
+	ThinPlateSpline { alpha: Option<f32>, kernel: RadialBasis },
+	MovingLeastSquares { mode: MlsMode, alpha: f32 },
+	/// Wendland kernels with a sparse solver.  Use this for thousands of points.  Radius is in pixels.
+	CompactSpline { alpha: f32, radius: f32 },
+}
+
+impl Default for WarpMethod {
+	fn default() -> Self {
+		WarpMethod::ThinPlateSpline { alpha: Some(DEFAULT_ALPHA), kernel: RadialBasis::ThinPlate }
+	}
+}
+
+impl WarpMethod {
+	/// Fit a warp which maps the source points onto the destination points.
+	pub fn fit(&self, source_points: &Vec<f32>, destination_points: &Vec<f32>) -> Box<dyn Warp> {
+		match *self {
+			WarpMethod::ThinPlateSpline { alpha: Some(alpha), kernel } => Box::new(ThinPlateSpline::new_with_kernel(source_points, destination_points, alpha, kernel)),
+			WarpMethod::ThinPlateSpline { alpha: None, kernel } => Box::new(ThinPlateSpline::new_with_automatic_alpha(source_points, destination_points, kernel)),
+			WarpMethod::MovingLeastSquares { mode, alpha } => Box::new(MovingLeastSquares::new(source_points, destination_points, mode, alpha)),
+			WarpMethod::CompactSpline { alpha, radius } => Box::new(CompactSpline::new(source_points, destination_points, alpha, radius)),
+		}
+	}
+}
+
+impl Warp for ThinPlateSpline {
+	fn transform(&self, points: &Vec<f32>) -> Vec<f32> {
+		ThinPlateSpline::transform(self, points)
+	}
+
+	fn transform_inverse(&self, points: &Vec<f32>) -> Option<Vec<f32>> {
+		Some(ThinPlateSpline::transform_inverse(self, points))
+	}
+
+	fn jacobian(&self, points: &Vec<f32>) -> Option<Vec<f32>> {
+		Some(ThinPlateSpline::jacobian(self, points))
+	}
+}
+
+impl Warp for MovingLeastSquares {
+	fn transform(&self, points: &Vec<f32>) -> Vec<f32> {
+		MovingLeastSquares::transform(self, points)
+	}
+
+	fn jacobian(&self, points: &Vec<f32>) -> Option<Vec<f32>> {
+		// The closed form for MLS is a mess of derivatives of the weights.  Central differences are plenty.
+		Some(numeric_jacobian(self, points))
+	}
+}
+
+impl Warp for CompactSpline {
+	fn transform(&self, points: &Vec<f32>) -> Vec<f32> {
+		CompactSpline::transform(self, points)
+	}
+
+	fn jacobian(&self, points: &Vec<f32>) -> Option<Vec<f32>> {
+		Some(CompactSpline::jacobian(self, points))
+	}
+}
+
+/// Central difference Jacobian of any warp, [dx'/dx, dx'/dy, dy'/dx, dy'/dy] per point.
+pub fn numeric_jacobian(warp: &dyn Warp, points: &Vec<f32>) -> Vec<f32> {
+	// Step size in pixels.  Small enough to be local, big enough to not drown in f32 rounding.
+	let h = 1e-2f32;
+	let mut offsets = Vec::with_capacity(points.len() * 4);
+	for pt in points.chunks_exact(2) {
+		offsets.extend([pt[0] - h, pt[1], pt[0] + h, pt[1], pt[0], pt[1] - h, pt[0], pt[1] + h]);
+	}
+	let f = warp.transform(&offsets);
+	f.chunks_exact(8).flat_map(|f| {
+		[(f[2] - f[0]) / (2.0*h), (f[6] - f[4]) / (2.0*h), (f[3] - f[1]) / (2.0*h), (f[7] - f[5]) / (2.0*h)]
+	}).collect()
+}
+
+/// Jacobian determinants of a warp sampled on a regular grid over an image.
+/// A determinant at or below zero means the warp folds the image over itself there, which shows up as ghosted
+/// duplicates in the output.  Almost always the cause is a pair of correspondences that cross each other.
+pub struct JacobianMap {
+	step: u32,
+	columns: u32,
+	rows: u32,
+	determinants: Vec<f32>,
+}
+
+impl JacobianMap {
+	/// Sample every `step` pixels over a width x height image.
+	/// Returns None if the image is empty or the warp doesn't provide a Jacobian.
+	pub fn new(warp: &dyn Warp, width: u32, height: u32, step: u32) -> Option<Self> {
+		assert!(step > 0);
+		if width == 0 || height == 0 {
+			return None;
+		}
+		let columns = width.div_ceil(step);
+		let rows = height.div_ceil(step);
+		let mut points = Vec::with_capacity((2 * columns * rows) as usize);
+		for y in 0..rows {
+			for x in 0..columns {
+				points.push((x * step) as f32);
+				points.push((y * step) as f32);
+			}
+		}
+		let jacobian = warp.jacobian(&points)?;
+		let determinants = jacobian.chunks_exact(4).map(|j| j[0]*j[3] - j[1]*j[2]).collect();
+		Some(Self {
+			step,
+			columns,
+			rows,
+			determinants,
+		})
+	}
+
+	/// The determinant at the sample nearest to the given pixel.
+	pub fn get_determinant(&self, x: u32, y: u32) -> f32 {
+		let column = (x / self.step).min(self.columns - 1);
+		let row = (y / self.step).min(self.rows - 1);
+		self.determinants[(row * self.columns + column) as usize]
+	}
+
+	pub fn has_fold_over(&self) -> bool {
+		self.determinants.iter().any(|d| *d <= 0.0)
+	}
+
+	/// The pixel positions of every sample where the warp folds over.
+	pub fn get_fold_over_points(&self) -> Vec<(u32, u32)> {
+		let mut result = vec![];
+		for row in 0..self.rows {
+			for column in 0..self.columns {
+				if self.determinants[(row * self.columns + column) as usize] <= 0.0 {
+					result.push((column * self.step, row * self.step));
+				}
+			}
+		}
+		result
+	}
+
+	/// The bounding boxes, (min_x, min_y, max_x, max_y) in pixels, of each connected region of fold over.
+	pub fn get_fold_over_regions(&self) -> Vec<(u32, u32, u32, u32)> {
+		let mut visited = vec![false; self.determinants.len()];
+		let mut regions = vec![];
+		for start in 0..self.determinants.len() {
+			if visited[start] || self.determinants[start] > 0.0 {
+				continue;
+			}
+			// Flood fill over 4-connected folded samples.
+			let (mut min_c, mut min_r, mut max_c, mut max_r) = (u32::MAX, u32::MAX, 0u32, 0u32);
+			let mut stack = vec![start];
+			visited[start] = true;
+			while let Some(idx) = stack.pop() {
+				let column = idx as u32 % self.columns;
+				let row = idx as u32 / self.columns;
+				min_c = min_c.min(column);
+				min_r = min_r.min(row);
+				max_c = max_c.max(column);
+				max_r = max_r.max(row);
+				let mut neighbors = vec![];
+				if column > 0 { neighbors.push(idx - 1); }
+				if column + 1 < self.columns { neighbors.push(idx + 1); }
+				if row > 0 { neighbors.push(idx - self.columns as usize); }
+				if row + 1 < self.rows { neighbors.push(idx + self.columns as usize); }
+				for n in neighbors {
+					if !visited[n] && self.determinants[n] <= 0.0 {
+						visited[n] = true;
+						stack.push(n);
+					}
+				}
+			}
+			regions.push((min_c * self.step, min_r * self.step, max_c * self.step, max_r * self.step));
+		}
+		regions
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_methods_interchangeable() {
+		let src = vec![0.0f32, 0.0, 10.0, 0.0, 0.0, 10.0, 10.0, 10.0];
+		let dst = vec![5.0f32, 5.0, 15.0, 5.0, 5.0, 15.0, 15.0, 15.0];
+		let methods = [
+			WarpMethod::ThinPlateSpline { alpha: Some(0.0), kernel: RadialBasis::ThinPlate },
+			WarpMethod::MovingLeastSquares { mode: MlsMode::Affine, alpha: 1.0 },
+			WarpMethod::MovingLeastSquares { mode: MlsMode::Rigid, alpha: 1.0 },
+			WarpMethod::CompactSpline { alpha: 0.0, radius: 15.0 },
+		];
+		for method in methods {
+			let warp = method.fit(&src, &dst);
+			let out = warp.transform(&vec![5.0, 5.0, 0.0, 0.0]);
+			assert!((out[0] - 10.0).abs() < 1e-3 && (out[1] - 10.0).abs() < 1e-3, "{method:?} gave {out:?}");
+			assert!((out[2] - 5.0).abs() < 1e-3 && (out[3] - 5.0).abs() < 1e-3, "{method:?} gave {out:?}");
+		}
+	}
+
+	#[test]
+	fn test_fold_over_regions() {
+		let src = vec![0.0f32, 0.0, 100.0, 0.0, 0.0, 100.0, 100.0, 100.0, 40.0, 50.0, 60.0, 50.0];
+		let crossed = vec![0.0f32, 0.0, 100.0, 0.0, 0.0, 100.0, 100.0, 100.0, 60.0, 50.0, 40.0, 50.0];
+		let straight = vec![0.0f32, 0.0, 100.0, 0.0, 0.0, 100.0, 100.0, 100.0, 45.0, 50.0, 65.0, 50.0];
+		let method = WarpMethod::ThinPlateSpline { alpha: Some(0.0), kernel: RadialBasis::ThinPlate };
+
+		let good = JacobianMap::new(method.fit(&src, &straight).as_ref(), 100, 100, 5).unwrap();
+		assert!(!good.has_fold_over());
+		assert!(good.get_fold_over_regions().is_empty());
+
+		let bad = JacobianMap::new(method.fit(&src, &crossed).as_ref(), 100, 100, 5).unwrap();
+		assert!(bad.has_fold_over());
+		assert!(bad.get_determinant(50, 50) <= 0.0);
+		let regions = bad.get_fold_over_regions();
+		assert_eq!(regions.len(), 1);
+		let (min_x, min_y, max_x, max_y) = regions[0];
+		assert!(min_x <= 50 && max_x >= 50 && min_y <= 50 && max_y >= 50);
+
+		// There's nothing to sample in an empty image.
+		assert!(JacobianMap::new(method.fit(&src, &crossed).as_ref(), 0, 100, 5).is_none());
+	}
+}